@@ -1,13 +1,22 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     env,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    str::FromStr,
+    sync::OnceLock,
 };
 
-use anyhow::{anyhow, bail, Context};
+use anyhow::{bail, Context};
+use cargo_platform::{Cfg, Platform};
 use rustc_version::Channel;
+use serde::de::DeserializeOwned;
 
-pub fn rust_version() -> anyhow::Result<()> {
+use crate::meta::VITA_TARGET;
+
+/// Checks that the active toolchain is able to build std for the Vita target,
+/// and that it is not older than `min_nightly`, if the package requires one.
+pub fn rust_toolchain(min_nightly: Option<&str>) -> anyhow::Result<()> {
     let rust_version = rustc_version::version_meta()?;
 
     if rust_version.channel > Channel::Nightly {
@@ -22,43 +31,365 @@ pub fn rust_version() -> anyhow::Result<()> {
         );
     }
 
+    let version = format!(
+        "{} ({})",
+        rust_version.semver,
+        rust_version
+            .commit_date
+            .as_deref()
+            .unwrap_or("unknown date")
+    );
+
+    if let (Some(min_nightly), Some(commit_date)) = (min_nightly, &rust_version.commit_date) {
+        // A nightly is built from the last commit of the previous day
+        let min_commit_date = previous_day(min_nightly).with_context(|| {
+            format!("Invalid `min_nightly` date `{min_nightly}`, expected YYYY-MM-DD")
+        })?;
+
+        if *commit_date < min_commit_date {
+            bail!(
+                "The package requires nightly-{min_nightly} or newer, but rustc is {version}. \
+                Do one of the following:\n \
+                - Run `rustup update nightly` to update the nightly toolchain\n \
+                - Run `rustup override set nightly-{min_nightly}` to use this version in the current directory"
+            );
+        }
+    }
+
+    let targets = rustc(&["--print", "target-list"])?;
+
+    if !targets.lines().any(|target| target.trim() == VITA_TARGET) {
+        bail!(
+            "rustc {version} does not support `{VITA_TARGET}` target, it is too old. \
+            Run `rustup update nightly` to update the nightly toolchain."
+        );
+    }
+
+    let sysroot = rustc(&["--print", "sysroot"])?;
+    let sysroot = Path::new(sysroot.trim());
+    let library = sysroot
+        .join("lib")
+        .join("rustlib")
+        .join("src")
+        .join("rust")
+        .join("library");
+
+    if !library.join("Cargo.lock").exists() {
+        // Toolchains installed by rustup are located in `$RUSTUP_HOME/toolchains/<name>`
+        let toolchain = env::var("RUSTUP_TOOLCHAIN").ok().or_else(|| {
+            sysroot
+                .parent()
+                .filter(|parent| parent.ends_with("toolchains"))
+                .and(sysroot.file_name())
+                .map(|name| name.to_string_lossy().to_string())
+        });
+        let toolchain = toolchain
+            .map(|toolchain| format!(" --toolchain {toolchain}"))
+            .unwrap_or_default();
+
+        bail!(
+            "The std sources are missing from {}, they are required to build std for the Vita target. \
+            Run `rustup component add rust-src{toolchain}` to install them.",
+            sysroot.display()
+        );
+    }
+
     Ok(())
 }
 
-pub fn set_cargo_config_env() -> anyhow::Result<()> {
+/// Runs `rustc` with the given arguments and returns its stdout.
+fn rustc(args: &[&str]) -> anyhow::Result<String> {
+    let rustc = env::var_os("RUSTC");
+    let output = Command::new(rustc.as_deref().unwrap_or_else(|| "rustc".as_ref()))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("running `rustc {}` command", args.join(" ")))?;
+
+    anyhow::ensure!(
+        output.status.success(),
+        "`rustc {}` failed: {}\n{}",
+        args.join(" "),
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    String::from_utf8(output.stdout).context("rustc output is not valid UTF-8")
+}
+
+/// The date before a `YYYY-MM-DD` date, in the same format.
+fn previous_day(date: &str) -> Option<String> {
+    let mut parts = date.splitn(3, '-').map(str::parse::<u32>);
+    let (year, month, day) = (
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+
+    let days_in_month = |year: u32, month: u32| match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+
+    let (year, month, day) = match (month, day) {
+        (1, 1) => (year.checked_sub(1)?, 12, 31),
+        (month, 1) => (year, month - 1, days_in_month(year, month - 1)),
+        (month, day) => (year, month, day - 1),
+    };
+
+    Some(format!("{year:04}-{month:02}-{day:02}"))
+}
+
+/// Runs `cargo config get` with the given arguments and returns its stdout.
+fn cargo_config_get(args: &[&str]) -> anyhow::Result<Vec<u8>> {
     let cargo = env::var_os("CARGO");
-    let mut child = Command::new(cargo.as_deref().unwrap_or_else(|| "cargo".as_ref()))
-        .args(["config", "get", "-Zunstable-options", "--format=json"])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
+    let output = Command::new(cargo.as_deref().unwrap_or_else(|| "cargo".as_ref()))
+        .args(["config", "get", "-Zunstable-options"])
+        .args(args)
         .stdin(Stdio::null())
-        .spawn()
-        .context("spawning `cargo config get` process")?;
-
-    let CargoConfig { env } = child
-        .stdout
-        .take()
-        .ok_or_else(|| anyhow!("`cargo config get` child process has no stdout"))
-        .and_then(|stdout| {
-            serde_json::from_reader(stdout)
-                .context("failed to deserialize `cargo config get` output")
-        })
-        .inspect_err(|_| {
-            let _ = child.kill();
-        })?;
+        .output()
+        .context("running `cargo config get` command")?;
+
+    anyhow::ensure!(
+        output.status.success(),
+        "`cargo config get` failed: {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(output.stdout)
+}
+
+/// The merged cargo configuration is read both before the arguments are parsed and by the build,
+/// so it is queried once per invocation.
+static CARGO_CONFIG: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Deserializes the parts of the merged cargo configuration we need.
+fn cargo_config<T: DeserializeOwned>() -> anyhow::Result<T> {
+    let stdout = if let Some(stdout) = CARGO_CONFIG.get() {
+        stdout
+    } else {
+        let stdout = cargo_config_get(&["--format=json"])?;
+        CARGO_CONFIG.get_or_init(|| stdout)
+    };
 
-    let status = child.wait().context("running `cargo config get` command")?;
-    anyhow::ensure!(status.success(), "`cargo config get` failed: {status:?}");
+    serde_json::from_slice(stdout).context("failed to deserialize `cargo config get` output")
+}
+
+/// Returns the directory relative `[env]` values of the given config key are resolved against,
+/// which is the parent of the `.cargo` directory of the config file that defines it.
+fn config_base_dir(key: &str) -> anyhow::Result<PathBuf> {
+    let stdout = cargo_config_get(&["--format=toml", "--show-origin", key])?;
+    let stdout = String::from_utf8_lossy(&stdout);
+
+    let origin = stdout
+        .lines()
+        .next()
+        .and_then(|line| line.rsplit_once(" # "))
+        .map(|(_, origin)| Path::new(origin.trim()));
+
+    match origin {
+        Some(origin) if origin.is_file() => origin
+            .parent()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .with_context(|| format!("config file {} has no parent", origin.display())),
+        // Values that come from environment variables or command line are relative to the current directory
+        _ => env::current_dir().context("Unable to get current directory"),
+    }
+}
 
-    env.iter()
-        .filter(|(key, _)| env::var_os(key).is_none())
-        .for_each(|(key, value)| env::set_var(key, value));
+/// Applies the `[env]` section of cargo config to the environment of this process,
+/// so that it is inherited by cargo and all the tools we run.
+/// Invalid entries do not stop the valid ones from being applied, and are all reported at the end.
+pub fn set_cargo_config_env() -> anyhow::Result<()> {
+    let CargoConfig { env } = cargo_config()?;
+
+    let errors = env
+        .into_iter()
+        .filter_map(|(key, value)| set_config_env_var(key, value).err())
+        .map(|err| format!("{err:#}"))
+        .collect::<Vec<_>>();
+
+    if !errors.is_empty() {
+        bail!(
+            "{} invalid entries:\n  {}",
+            errors.len(),
+            errors.join("\n  ")
+        );
+    }
 
     Ok(())
 }
 
+fn set_config_env_var(key: String, value: serde_json::Value) -> anyhow::Result<()> {
+    let value = serde_json::from_value::<EnvValue>(value).with_context(|| {
+        format!(
+            "Invalid `env.{key}` entry, expected a string or a table with a string `value` \
+            and optional `force` and `relative` flags"
+        )
+    })?;
+
+    let (value, force, relative) = match value {
+        EnvValue::Value(value) => (value, false, false),
+        EnvValue::Table {
+            value,
+            force,
+            relative,
+        } => (value, force, relative),
+    };
+
+    if !force && env::var_os(&key).is_some() {
+        return Ok(());
+    }
+
+    if relative {
+        let base = config_base_dir(&format!("env.{key}.value"))
+            .with_context(|| format!("Unable to resolve relative path of `env.{key}`"))?;
+        env::set_var(key, base.join(value));
+    } else {
+        env::set_var(key, value);
+    }
+
+    Ok(())
+}
+
+/// Returns the rustflags from `[target.<target>]`, matching `[target.'cfg(..)']` or `[build]` sections
+/// of the cargo configuration, the same way cargo picks them when neither `RUSTFLAGS`
+/// nor `CARGO_ENCODED_RUSTFLAGS` is set.
+pub fn config_rustflags(target: &str) -> anyhow::Result<Vec<String>> {
+    let RustflagsConfig {
+        build,
+        target: targets,
+    } = cargo_config()?;
+
+    let target_cfg = target_cfg(target)?;
+    let mut flags = None::<Vec<String>>;
+
+    // Like cargo, the flags of `[target.<triple>]` and of every matching `[target.'cfg(..)']`
+    // are joined, and `[build]` is only used when none of them set rustflags
+    for (key, config) in targets {
+        let Some(rustflags) = config.rustflags else {
+            continue;
+        };
+
+        let platform = Platform::from_str(&key)
+            .with_context(|| format!("Invalid target `{key}` in cargo config"))?;
+
+        if platform.matches(target, &target_cfg) {
+            flags
+                .get_or_insert_with(Vec::new)
+                .extend(rustflags.into_vec());
+        }
+    }
+
+    Ok(flags
+        .or_else(|| build.rustflags.map(Rustflags::into_vec))
+        .unwrap_or_default())
+}
+
+/// The cfg values of the target, to evaluate `[target.'cfg(..)']` sections of cargo config against.
+fn target_cfg(target: &str) -> anyhow::Result<Vec<Cfg>> {
+    rustc(&["--print", "cfg", "--target", target])?
+        .lines()
+        .map(|line| {
+            Cfg::from_str(line).with_context(|| format!("Unable to parse target cfg `{line}`"))
+        })
+        .collect()
+}
+
 #[derive(serde::Deserialize)]
 struct CargoConfig {
     #[serde(default)]
-    env: HashMap<String, String>,
+    env: HashMap<String, serde_json::Value>,
+}
+
+/// An `[env]` entry, either `KEY = "value"` or `KEY = { value = "value", force = true, relative = true }`.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum EnvValue {
+    Value(String),
+    Table {
+        value: String,
+        #[serde(default)]
+        force: bool,
+        #[serde(default)]
+        relative: bool,
+    },
+}
+
+#[derive(serde::Deserialize)]
+struct RustflagsConfig {
+    #[serde(default)]
+    build: BuildConfig,
+    /// Sorted like cargo does, so that the flags of several matching sections are joined in the same order.
+    #[serde(default)]
+    target: BTreeMap<String, TargetConfig>,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct BuildConfig {
+    rustflags: Option<Rustflags>,
+}
+
+#[derive(serde::Deserialize)]
+struct TargetConfig {
+    rustflags: Option<Rustflags>,
+}
+
+/// Cargo accepts rustflags either as a space-separated string or as an array of strings.
+#[derive(serde::Deserialize, Clone)]
+#[serde(untagged)]
+enum Rustflags {
+    String(String),
+    Array(Vec<String>),
+}
+
+impl Rustflags {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            Rustflags::String(s) => s.split_whitespace().map(String::from).collect(),
+            Rustflags::Array(flags) => flags,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previous_day_within_a_month() {
+        assert_eq!(previous_day("2024-06-15").as_deref(), Some("2024-06-14"));
+    }
+
+    #[test]
+    fn previous_day_crosses_months_and_years() {
+        assert_eq!(previous_day("2024-05-01").as_deref(), Some("2024-04-30"));
+        assert_eq!(previous_day("2024-01-01").as_deref(), Some("2023-12-31"));
+    }
+
+    #[test]
+    fn previous_day_handles_leap_years() {
+        assert_eq!(previous_day("2024-03-01").as_deref(), Some("2024-02-29"));
+        assert_eq!(previous_day("2023-03-01").as_deref(), Some("2023-02-28"));
+        assert_eq!(previous_day("2000-03-01").as_deref(), Some("2000-02-29"));
+        assert_eq!(previous_day("1900-03-01").as_deref(), Some("1900-02-28"));
+    }
+
+    #[test]
+    fn previous_day_rejects_invalid_dates() {
+        assert_eq!(previous_day("2024-02-30"), None);
+        assert_eq!(previous_day("2024-13-01"), None);
+        assert_eq!(previous_day("2024-06"), None);
+        assert_eq!(previous_day("nightly"), None);
+    }
 }
@@ -1,25 +1,39 @@
 use std::{
-    env,
-    fs::File,
-    io::{self, BufReader},
+    cell::OnceCell,
+    collections::{BTreeSet, HashMap},
+    fs::{self, File},
+    io::{BufRead, BufReader},
     path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
-use crate::{check, commands::build::unit_graph::try_parse_unit_graph, ftp};
+use crate::{
+    check, commands::build::unit_graph::try_parse_unit_graph, ftp, imports, nids, nids::NidDb,
+    sdk::VitaSdk,
+};
 use anyhow::{bail, Context};
-use cargo_metadata::{camino::Utf8PathBuf, Artifact, Message, Package};
-use clap::{command, Args, Subcommand};
+use cargo_metadata::{
+    camino::{Utf8Path, Utf8PathBuf},
+    Artifact, Message, Package,
+};
+use clap::{Args, Subcommand};
 use colored::Colorize;
-use either::Either;
 use log::{info, warn};
-use tee::TeeReader;
 use walkdir::WalkDir;
 
-use crate::meta::{parse_crate_metadata, PackageMetadata, TitleId, VITA_TARGET};
+use crate::meta::{artifact_kind, PackageMetadata, TitleId, WorkspaceMetadata};
 
-use super::{ConnectionArgs, Executor, OptionalConnectionArgs, Run};
+use self::{
+    manifest::BuildManifest,
+    size::{SizeEntry, SizeLimitViolation, SizeReport},
+};
+use super::{ConnectionArgs, Executor, OptionalConnectionArgs, Run, SymbolStoreArgs};
 
+mod linker;
+mod manifest;
+mod plugin;
+mod size;
+mod stubs;
 mod unit_graph;
 
 #[derive(Args, Debug)]
@@ -31,6 +45,28 @@ pub struct Build {
     #[arg(long, env="VITA_DEFAULT_TITLE_ID", value_parser = clap::value_parser!(TitleId))]
     default_title_id: Option<TitleId>,
 
+    #[command(flatten)]
+    symbol_store: SymbolStoreArgs,
+
+    /// Print a size breakdown of the built elf files by section, crate and symbol,
+    /// compared to the previous build.
+    #[arg(long)]
+    size_report: bool,
+
+    /// The number of crates and symbols shown in the size report.
+    #[arg(long, default_value_t = 20)]
+    size_report_top: usize,
+
+    /// A build manifest (`*.manifest.json` next to the elf) to compare the size report against.
+    /// Defaults to the manifest of the previous build.
+    #[arg(long)]
+    size_baseline: Option<Utf8PathBuf>,
+
+    /// Skip analyzing the cargo unit graph for known problems before the build,
+    /// which takes an extra cargo invocation.
+    #[arg(long)]
+    no_preflight: bool,
+
     /// Pass additional options through to the `cargo` command.
     ///
     /// All arguments after the first `--`, or starting with the first unrecognized
@@ -49,6 +85,8 @@ enum BuildCmd {
     Eboot(Eboot),
     Sfo,
     Vpk(Vpk),
+    /// Builds a taiHEN plugin, `.suprx` for user plugins or `.skprx` for kernel plugins.
+    Plugin(Plugin),
 }
 
 #[derive(Args, Debug)]
@@ -75,30 +113,48 @@ struct Vpk {
     destination: String,
 }
 
+#[derive(Args, Debug)]
+struct Plugin {
+    /// Uploads the plugin files to the destination folder
+    #[arg(long, default_value = "false")]
+    upload: bool,
+    /// A directory on Vita where a plugin will be saved. Slash in the end indicates that it's a directory.
+    #[arg(long, short = 'd', default_value = "ur0:tai/")]
+    destination: String,
+    #[command(flatten)]
+    connection: OptionalConnectionArgs,
+}
+
 struct BuildContext<'a> {
     command: &'a Build,
-    sdk: String,
+    sdk: VitaSdk,
+    workspace: WorkspaceMetadata,
+    /// Metadata of the default package of the workspace.
+    // FIXME: move build-std to .cargo/config.toml, since it is shared by ALL of the crates built,
+    // but the metadata is per-crate. This still works correctly when building only a single workspace crate.
+    meta: PackageMetadata,
+    /// NID databases of the SDK, loaded when the imports of the first artifact are checked.
+    nid_dbs: OnceCell<Vec<NidDb>>,
 }
 
 impl<'a> BuildContext<'a> {
     pub fn new(command: &'a Build) -> anyhow::Result<Self> {
-        let sdk = std::env::var("VITASDK");
-        let sdk = sdk.or_else(|_| {
-            bail!(
-                "VITASDK environment variable isn't set. Please install the SDK \
-                    from https://vitasdk.org/ and set the VITASDK environment variable."
-            )
-        })?;
-
-        Ok(Self { command, sdk })
-    }
+        let mut sdk = VitaSdk::from_env()?;
+        let workspace = WorkspaceMetadata::load()?;
+        let (meta, _) = workspace.package_metadata()?;
 
-    fn sdk(&self, path: &str) -> PathBuf {
-        Path::new(&self.sdk).join(path)
-    }
+        // Plugins are started by `module_start`, and must not link the C runtime startup code of apps
+        if matches!(command.cmd, BuildCmd::Plugin(_)) {
+            sdk.add_rustflags(["-Clink-arg=-nostartfiles".to_string()]);
+        }
 
-    fn sdk_binary(&self, binary: &str) -> PathBuf {
-        self.sdk("bin").join(binary)
+        Ok(Self {
+            command,
+            sdk,
+            workspace,
+            meta,
+            nid_dbs: OnceCell::new(),
+        })
     }
 }
 
@@ -112,9 +168,13 @@ struct ExecutableArtifact {
 }
 
 impl ExecutableArtifact {
-    fn new(artifact: Artifact) -> anyhow::Result<Self> {
-        let (meta, package, _) = parse_crate_metadata(Some(&artifact))?;
-        let package = package.context("artifact does not have a package")?;
+    fn new(
+        artifact: Artifact,
+        workspace: &WorkspaceMetadata,
+        default_title_id: Option<&TitleId>,
+    ) -> anyhow::Result<Self> {
+        let (meta, package) = workspace.artifact_metadata(&artifact, default_title_id)?;
+        let package = package.context("artifact does not have a package")?.clone();
 
         let executable = artifact
             .executable
@@ -129,33 +189,100 @@ impl ExecutableArtifact {
             elf: executable,
         })
     }
+
+    /// The output path of a plugin, depending on whether it is a kernel or a user plugin.
+    fn plugin_path(&self) -> Utf8PathBuf {
+        let extension = if self.meta.plugin.kernel {
+            "skprx"
+        } else {
+            "suprx"
+        };
+
+        self.elf.with_extension(extension)
+    }
+
+    /// The output path of the vpk. Tests and examples get a readable name instead of
+    /// the hashed file name of their elf, e.g. `my-app-test-integration.vpk`.
+    fn vpk_path(&self) -> Utf8PathBuf {
+        match artifact_kind(&self.artifact) {
+            Some(kind) => self.elf.with_file_name(format!(
+                "{}-{kind}-{}.vpk",
+                self.package.name, self.artifact.target.name
+            )),
+            None => self.elf.with_extension("vpk"),
+        }
+    }
+
+    /// Returns the files of the assets directory, paired with their paths inside the vpk.
+    fn assets(&self) -> anyhow::Result<Vec<(PathBuf, PathBuf)>> {
+        let Some(assets) = &self.meta.assets else {
+            return Ok(Vec::new());
+        };
+
+        let assets = self
+            .artifact
+            .manifest_path
+            .parent()
+            .context("Unable to get target manifest directory")?
+            .join(assets);
+
+        WalkDir::new(&assets)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .map(|file| {
+                let path_in_vpk = file
+                    .path()
+                    .strip_prefix(&assets)
+                    .context("Unable to strip VPK prefix")?
+                    .to_owned();
+
+                Ok((file.into_path(), path_in_vpk))
+            })
+            .collect()
+    }
 }
 
 impl Executor for Build {
     fn execute(&self) -> anyhow::Result<()> {
-        check::rust_version()?;
-
-        let ctx = BuildContext::new(self)?;
+        let mut ctx = BuildContext::new(self)?;
+        check::rust_toolchain(ctx.meta.min_nightly.as_deref())?;
+
+        ctx.generate_stubs()?;
+        let mut artifacts = ctx.build_elf()?;
+        resolve_duplicate_title_ids(
+            artifacts
+                .iter_mut()
+                .map(|art| (art.artifact.target.name.as_str(), &mut art.meta))
+                .collect(),
+        );
+
+        if self.size_report {
+            for art in &artifacts {
+                ctx.size_report(art)?;
+            }
+        }
 
         match &self.cmd {
-            BuildCmd::Elf => {
-                ctx.build_elf()?;
-            }
+            BuildCmd::Elf => {}
             BuildCmd::Velf => {
-                for art in ctx.build_elf()? {
-                    ctx.strip(&art)?;
-                    ctx.velf(&art)?;
+                for art in &artifacts {
+                    ctx.archive(art)?;
+                    ctx.strip(art)?;
+                    ctx.velf(art, None)?;
                 }
             }
             BuildCmd::Eboot(args) => {
-                let artifacts = ctx.build_elf()?;
-
                 for art in &artifacts {
+                    ctx.archive(art)?;
                     ctx.strip(art)?;
-                    ctx.velf(art)?;
+                    ctx.velf(art, None)?;
+                    ctx.check_imports(art)?;
                     ctx.eboot(art)?;
                 }
 
+                ctx.check_size_limits(&artifacts, false)?;
+
                 if args.update {
                     let files = ctx.eboot_uploads(&artifacts)?;
                     upload(&files, &args.connection.clone().required()?)?;
@@ -166,21 +293,23 @@ impl Executor for Build {
                 }
             }
             BuildCmd::Sfo => {
-                for art in ctx.build_elf()? {
-                    ctx.sfo(&art)?;
+                for art in &artifacts {
+                    ctx.sfo(art)?;
                 }
             }
             BuildCmd::Vpk(args) => {
-                let artifacts = ctx.build_elf()?;
-
                 for art in &artifacts {
+                    ctx.archive(art)?;
                     ctx.strip(art)?;
-                    ctx.velf(art)?;
+                    ctx.velf(art, None)?;
+                    ctx.check_imports(art)?;
                     ctx.eboot(art)?;
                     ctx.sfo(art)?;
                     ctx.vpk(art)?;
                 }
 
+                ctx.check_size_limits(&artifacts, true)?;
+
                 let mut upload_files = Vec::new();
 
                 if args.upload {
@@ -199,6 +328,21 @@ impl Executor for Build {
                     ctx.run(&artifacts, &args.eboot.connection.clone().required()?)?;
                 }
             }
+            BuildCmd::Plugin(args) => {
+                for art in &artifacts {
+                    ctx.archive(art)?;
+                    // Exported symbols are validated against the elf before it is stripped
+                    let exports = plugin::exports_file(art)?;
+                    ctx.strip(art)?;
+                    ctx.velf(art, Some(&exports))?;
+                    ctx.plugin(art)?;
+                }
+
+                if args.upload {
+                    let files = ctx.plugin_uploads(&artifacts, &args.destination);
+                    upload(&files, &args.connection.clone().required()?)?;
+                }
+            }
         }
 
         Ok(())
@@ -206,49 +350,55 @@ impl Executor for Build {
 }
 
 impl BuildContext<'_> {
-    fn build_elf(&self) -> anyhow::Result<Vec<ExecutableArtifact>> {
-        let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
-
-        let rust_flags = env::var("RUSTFLAGS").unwrap_or_default()
-            + " --cfg mio_unsupported_force_poll_poll --cfg mio_unsupported_force_waker_pipe";
-
-        // FIXME: move build-std to .cargo/config.toml, since it is shared by ALL of the crates built,
-        // but the metadata is per-crate. This still works correctly when building only a single workspace crate.
-        let (meta, _, _) = parse_crate_metadata(None)?;
-
-        let command = || {
-            let mut command = Command::new(&cargo);
+    fn generate_stubs(&mut self) -> anyhow::Result<()> {
+        if self.meta.imports.is_empty() {
+            return Ok(());
+        }
 
-            if let Ok(path) = env::var("PATH") {
-                let sdk_path = Path::new(&self.sdk).join("bin");
-                let path = format!("{}:{path}", sdk_path.display());
-                command.env("PATH", path);
+        let (_, package) = self.workspace.package_metadata()?;
+        let package = package.context("Imports require a package")?;
+        let crate_dir = package
+            .manifest_path
+            .parent()
+            .context("Unable to get package manifest directory")?;
+        let out_dir = self
+            .workspace
+            .target_directory()
+            .join("vita-stubs")
+            .join(&package.name);
+
+        let stubs = stubs::generate(&self.sdk, &self.meta.imports, crate_dir, &out_dir)?;
+
+        // Link args are only used when linking the binaries, and are ignored by the other crates
+        self.sdk
+            .add_rustflags([format!("-Clink-arg=@{}", stubs.link_args)]);
+
+        // Cargo does not track the libraries passed to the linker, so the binaries are relinked by hand
+        if stubs.changed {
+            let mut command = self.sdk.cargo("clean", &self.meta.build_std);
+            command.arg("-p").arg(&package.name);
+
+            info!(
+                "{}: {command:?}",
+                "Cleaning the package to relink it".blue()
+            );
+
+            if !command.status()?.success() {
+                bail!("cargo clean failed");
             }
+        }
 
-            command
-                .env("RUSTFLAGS", &rust_flags)
-                .env("TARGET_CC", "arm-vita-eabi-gcc")
-                .env("TARGET_CXX", "arm-vita-eabi-g++")
-                .pass_path_env("OPENSSL_LIB_DIR", || self.sdk("arm-vita-eabi").join("lib"))
-                .pass_path_env("OPENSSL_INCLUDE_DIR", || {
-                    self.sdk("arm-vita-eabi").join("include")
-                })
-                .pass_path_env("PKG_CONFIG", || self.sdk_binary("arm-vita-eabi-pkg-config"))
-                .env("VITASDK", &self.sdk)
-                .arg("build")
-                .arg("-Z")
-                .arg(format!("build-std={}", &meta.build_std))
-                .arg("--target")
-                .arg(VITA_TARGET)
-                .arg("--message-format=json-render-diagnostics")
-                .args(&self.command.cargo_args);
+        Ok(())
+    }
 
-            command
-        };
+    fn build_elf(&self) -> anyhow::Result<Vec<ExecutableArtifact>> {
+        let (message_format, _) = MessageFormat::from_cargo_args(&self.command.cargo_args);
 
-        let hints = try_parse_unit_graph(command()).ok();
+        if !self.command.no_preflight {
+            self.preflight_checks();
+        }
 
-        let mut command = command();
+        let mut command = self.cargo_build();
         command
             .stdin(Stdio::inherit())
             .stdout(Stdio::piped())
@@ -258,47 +408,52 @@ impl BuildContext<'_> {
 
         let mut process = command.spawn().context("Unable to spawn build process")?;
         let stdout = process.stdout.take().context("Build failed")?;
-        let stdout = if log::max_level() >= log::LevelFilter::Trace {
-            Either::Left(BufReader::new(TeeReader::new(stdout, io::stdout())))
-        } else {
-            Either::Right(BufReader::new(stdout))
-        };
-
-        let message_stream = Message::parse_stream(stdout);
+        let trace = log::max_level() >= log::LevelFilter::Trace;
 
         let mut artifacts = Vec::new();
+        let mut undefined_references = BTreeSet::new();
+
+        for line in BufReader::new(stdout).lines() {
+            let line = line.context("Unable to read cargo output")?;
+            let message = serde_json::from_str::<Message>(&line)
+                .unwrap_or_else(|_| Message::TextLine(line.clone()));
+
+            // Compiler messages rendered by cargo-vita are not forwarded, the same way cargo doesn't
+            let rendered_here =
+                message_format.render && matches!(message, Message::CompilerMessage(_));
+            if trace || (message_format.forward && !rendered_here) {
+                println!("{line}");
+            }
 
-        for message in message_stream {
-            match message.context("Unable to parse cargo output")? {
+            match message {
                 Message::CompilerArtifact(art) if art.executable.is_some() => {
-                    artifacts.push(ExecutableArtifact::new(art)?);
+                    artifacts.push(ExecutableArtifact::new(
+                        art,
+                        &self.workspace,
+                        self.command.default_title_id.as_ref(),
+                    )?);
                 }
+                Message::CompilerMessage(msg) => {
+                    if let Some(rendered) = &msg.message.rendered {
+                        if message_format.render {
+                            eprint!("{rendered}");
+                        }
+
+                        undefined_references.extend(
+                            linker::undefined_references(rendered).map(ToString::to_string),
+                        );
+                    }
+                }
+                Message::TextLine(line) if !message_format.forward => eprintln!("{line}"),
                 _ => {}
             }
         }
 
         if !process.wait_with_output()?.status.success() {
-            if let Some(hints) = hints {
-                if hints.strip_symbols() {
-                    warn!(
-                        "{warn}\n \
-                        Symbols in elf are required by `{velf}` to create a velf file.\n \
-                        Please remove `{strip_true}` or `{strip_symbols}` from your Cargo.toml.\n \
-                        If you want to optimize for the binary size, replace it \
-                        with `{strip_debug}` to strip debug section.\n \
-                        If you want to strip the symbol data from the resulting \
-                        binary, set `{strip_velf}` in `{vita_section}` \
-                        section of your Cargo.toml, this would strip the symbols from the velf.",
-                        warn = "Stripping symbols from ELF is unsupported.".yellow(),
-                        velf = "vita-elf-create".cyan(),
-                        strip_true = "strip=true".cyan(),
-                        strip_symbols = "strip=\"symbols\"".cyan(),
-                        strip_debug = "strip=\"debuginfo\"".cyan(),
-                        strip_velf = "strip_symbols = true".cyan(),
-                        vita_section = format!("[package.metadata.vita.{}]", hints.profile).cyan()
-                    );
-                }
-            }
+            linker::explain_undefined_references(
+                &undefined_references,
+                &nids::default_db_path(self.sdk.root()),
+            );
 
             bail!("cargo build failed")
         }
@@ -306,6 +461,89 @@ impl BuildContext<'_> {
         Ok(artifacts)
     }
 
+    /// The `cargo build` command with the arguments of this invocation.
+    fn cargo_build(&self) -> Command {
+        let (message_format, cargo_args) = MessageFormat::from_cargo_args(&self.command.cargo_args);
+
+        let mut command = self.sdk.cargo("build", &self.meta.build_std);
+        command.arg(message_format.cargo_arg()).args(&cargo_args);
+        command
+    }
+
+    /// Analyzes the unit graph before the build starts, and reports all known problems at once.
+    fn preflight_checks(&self) {
+        let Ok(hints) = try_parse_unit_graph(self.cargo_build()) else {
+            return;
+        };
+
+        let diagnostics = hints.diagnostics(&self.meta.build_std);
+
+        if !diagnostics.is_empty() {
+            warn!(
+                "{}",
+                format!(
+                    "Pre-flight checks found {} potential problem(s):",
+                    diagnostics.len()
+                )
+                .yellow()
+            );
+
+            for diagnostic in &diagnostics {
+                warn!("{diagnostic}");
+            }
+        }
+    }
+
+    /// Explains a `vita-elf-create` failure caused by stripped symbols.
+    /// Only needed when the pre-flight checks were skipped, since they report it as well.
+    fn explain_velf_failure(&self) {
+        if !self.command.no_preflight {
+            return;
+        }
+
+        if let Ok(hints) = try_parse_unit_graph(self.cargo_build()) {
+            for diagnostic in hints.strip_symbols_diagnostics() {
+                warn!("{diagnostic}");
+            }
+        }
+    }
+
+    fn size_report(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
+        let manifest_path = BuildManifest::path(&art.elf);
+        let mut manifest = BuildManifest::load(&manifest_path).unwrap_or_default();
+
+        let baseline = match &self.command.size_baseline {
+            Some(path) => BuildManifest::load(path)?.size,
+            None => manifest.size.take(),
+        };
+
+        let report = SizeReport::from_elf(&art.elf)?;
+
+        info!("{} {}", "Size report for".blue(), art.elf);
+        report.print(baseline.as_ref(), self.command.size_report_top);
+
+        manifest.size = Some(report);
+        manifest.save(&manifest_path)
+    }
+
+    fn archive(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
+        // Must run before `strip`, so that the archived elf keeps all of its symbols
+        // Tests and examples have titles of their own, so that they don't push the app out of the store
+        let title = art
+            .meta
+            .title_id
+            .as_ref()
+            .map_or_else(|| art.artifact.target.name.clone(), ToString::to_string);
+
+        self.command
+            .symbol_store
+            .store()?
+            .archive(&art.elf, &title)
+            .context("Unable to archive elf to the symbol store")?;
+
+        Ok(())
+    }
+
     fn strip(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
         // Try to guess if the elf was built with debug or release profile.
         // This intentionally uses components() instead of as_str() to
@@ -329,7 +567,7 @@ impl BuildContext<'_> {
             return Ok(());
         }
 
-        let mut command = Command::new(self.sdk_binary("arm-vita-eabi-strip"));
+        let mut command = Command::new(self.sdk.binary("arm-vita-eabi-strip"));
 
         command
             .arg("--strip-unneeded")
@@ -347,14 +585,18 @@ impl BuildContext<'_> {
         Ok(())
     }
 
-    fn velf(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
-        let mut command = Command::new(self.sdk_binary("vita-elf-create"));
+    fn velf(&self, art: &ExecutableArtifact, exports: Option<&Utf8Path>) -> anyhow::Result<()> {
+        let mut command = Command::new(self.sdk.binary("vita-elf-create"));
         let elf = &art.elf;
         let velf = elf.with_extension("velf");
 
+        if let Some(exports) = exports {
+            command.arg("-e").arg(exports);
+        }
+
         command
             .arg(elf)
-            .arg(velf)
+            .arg(&velf)
             .stdin(Stdio::inherit())
             .stdout(Stdio::inherit())
             .stderr(Stdio::inherit());
@@ -362,20 +604,139 @@ impl BuildContext<'_> {
         info!("{}: {command:?}", "Creating velf".blue());
 
         if !command.status()?.success() {
+            self.explain_velf_failure();
             bail!("vita-elf-create failed");
         }
 
+        self.command
+            .symbol_store
+            .store()?
+            .record_module(elf, &velf)
+            .context("Unable to record the module in the symbol store")
+    }
+
+    /// The NID databases of the SDK, which are loaded once for all of the artifacts.
+    /// If they are missing, imports are checked by library names only.
+    fn nid_dbs(&self) -> &[NidDb] {
+        self.nid_dbs.get_or_init(|| {
+            NidDb::load_all(&nids::default_db_path(self.sdk.root())).unwrap_or_else(|err| {
+                warn!(
+                    "{} {err:?}",
+                    "Unable to load NID database, imports are checked by library names only:"
+                        .yellow()
+                );
+                Vec::new()
+            })
+        })
+    }
+
+    fn check_imports(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
+        let fself = art
+            .meta
+            .fself_options()
+            .with_context(|| format!("Invalid fself options of {}", art.package.name))?;
+
+        if !fself.is_safe() && art.meta.firmware.is_empty() {
+            return Ok(());
+        }
+
+        let mut imports = imports::read_imports(&art.elf)?;
+        let dbs = self.nid_dbs();
+
+        for import in &mut imports {
+            import.resolve_names(dbs);
+        }
+
+        // Modules imported by the package itself are not part of the firmware
+        let crate_dir = art
+            .package
+            .manifest_path
+            .parent()
+            .context("Unable to get package manifest directory")?;
+        let package_imports = stubs::imports_db(&art.meta.imports, crate_dir)?;
+        let system_imports = imports
+            .iter()
+            .filter(|import| package_imports.find_library(import.library_nid).is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if fself.is_safe() {
+            let offending = imports
+                .iter()
+                .filter_map(|import| {
+                    imports::unsafe_only_reason(import, dbs)
+                        .map(|reason| format!("{} {import}: {reason}", import.kind))
+                })
+                .collect::<Vec<_>>();
+
+            if !offending.is_empty() {
+                bail!(
+                    "{} is built as a safe app, but imports symbols that are only available to unsafe apps:\n \
+                    {}\n\
+                    Remove these imports, or set `safe = false` in `[package.metadata.vita.fself]` of your Cargo.toml.",
+                    art.package.name,
+                    offending.join("\n ")
+                );
+            }
+        }
+
+        for firmware in &art.meta.firmware {
+            let firmware_dbs = imports::firmware_dbs(dbs, firmware);
+
+            if firmware_dbs.is_empty() {
+                bail!("No NID database for firmware {firmware} found");
+            }
+
+            let unresolved = imports::unresolved_imports(&system_imports, dbs, &firmware_dbs);
+
+            if !unresolved.is_empty() {
+                bail!(
+                    "{} imports symbols that would not resolve on firmware {firmware}:\n {}",
+                    art.package.name,
+                    unresolved
+                        .iter()
+                        .map(|(import, reason)| format!("{} {import}: {reason}", import.kind))
+                        .collect::<Vec<_>>()
+                        .join("\n ")
+                );
+            }
+        }
+
         Ok(())
     }
 
     fn eboot(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
-        let mut command = Command::new(self.sdk_binary("vita-make-fself"));
+        let mut command = Command::new(self.sdk.binary("vita-make-fself"));
         let elf = &art.elf;
         let velf = elf.with_extension("velf");
         let eboot = elf.with_extension("self");
 
+        let fself = art
+            .meta
+            .fself_options()
+            .with_context(|| format!("Invalid fself options of {}", art.package.name))?;
+
+        // Only `safe = false` counts as an opt-in, unsafe apps built from raw flags are likely a mistake
+        let opted_in = art.meta.fself.as_ref().and_then(|f| f.safe) == Some(false);
+
+        if !fself.is_safe() && !opted_in {
+            warn!(
+                "{} {}\n \
+                If this is intended, set `{}` in `{}` section of your Cargo.toml.",
+                art.package.name,
+                "is built as an unsafe app, with access to restricted APIs.".yellow(),
+                "safe = false".cyan(),
+                "[package.metadata.vita.fself]".cyan()
+            );
+        }
+
+        let manifest_path = BuildManifest::path(elf);
+        let mut manifest = BuildManifest::load(&manifest_path).unwrap_or_default();
+        manifest.fself = Some(fself.clone());
+        manifest.save(&manifest_path)?;
+
         command
-            .args(&art.meta.vita_make_fself_flags)
+            .args(fself.to_flags())
             .arg(&velf)
             .arg(&eboot)
             .stdin(Stdio::inherit())
@@ -391,8 +752,30 @@ impl BuildContext<'_> {
         Ok(())
     }
 
+    fn plugin(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
+        let mut command = Command::new(self.sdk.binary("vita-make-fself"));
+        let velf = art.elf.with_extension("velf");
+        let plugin = art.plugin_path();
+
+        command
+            .args(&art.meta.plugin.vita_make_fself_flags)
+            .arg(&velf)
+            .arg(&plugin)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+
+        info!("{}: {command:?}", "Creating plugin".blue());
+
+        if !command.status()?.success() {
+            bail!("vita-make-fself failed");
+        }
+
+        Ok(())
+    }
+
     fn sfo(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
-        let mut command = Command::new(self.sdk_binary("vita-mksfoex"));
+        let mut command = Command::new(self.sdk.binary("vita-mksfoex"));
         let elf = &art.elf;
         let sfo = elf.with_extension("sfo");
 
@@ -432,37 +815,18 @@ impl BuildContext<'_> {
 
     fn vpk(&self, art: &ExecutableArtifact) -> anyhow::Result<()> {
         let elf = &art.elf;
-        let vpk = elf.with_extension("vpk");
+        let vpk = art.vpk_path();
         let eboot = elf.with_extension("self");
         let sfo = elf.with_extension("sfo");
 
-        let mut command = Command::new(self.sdk_binary("vita-pack-vpk"));
+        let mut command = Command::new(self.sdk.binary("vita-pack-vpk"));
         command.arg("-s").arg(sfo);
         command.arg("-b").arg(eboot);
 
-        if let Some(assets) = &art.meta.assets {
-            let assets = art
-                .artifact
-                .manifest_path
-                .parent()
-                .context("Unable to get target manifest directory")?
-                .join(assets);
-
-            let files = WalkDir::new(&assets)
-                .into_iter()
-                .filter_map(Result::ok)
-                .filter(|e| e.file_type().is_file());
-
-            for file in files {
-                command.arg("--add").arg(format!(
-                    "{}={}",
-                    file.path().display(), // path on FS
-                    file.path()
-                        .strip_prefix(&assets)
-                        .context("Unable to strip VPK prefix")?
-                        .display()  // path in VPK
-                ));
-            }
+        for (path, path_in_vpk) in art.assets()? {
+            command
+                .arg("--add")
+                .arg(format!("{}={}", path.display(), path_in_vpk.display()));
         }
 
         command
@@ -480,6 +844,132 @@ impl BuildContext<'_> {
         Ok(())
     }
 
+    fn check_size_limits(&self, artifacts: &[ExecutableArtifact], vpk: bool) -> anyhow::Result<()> {
+        let mut violations = Vec::new();
+
+        for art in artifacts {
+            violations.extend(self.size_limit_violations(art, vpk)?);
+        }
+
+        if !violations.is_empty() {
+            let violations = violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            bail!("Size limits exceeded:\n{violations}");
+        }
+
+        Ok(())
+    }
+
+    fn size_limit_violations(
+        &self,
+        art: &ExecutableArtifact,
+        vpk: bool,
+    ) -> anyhow::Result<Vec<SizeLimitViolation>> {
+        let limits = &art.meta.size_limits;
+        let mut violations = Vec::new();
+
+        let file_size = |path: &Path| {
+            fs::metadata(path)
+                .map(|m| m.len())
+                .with_context(|| format!("Unable to get the size of {}", path.display()))
+        };
+
+        if let Some(limit) = limits.eboot {
+            let eboot = art.elf.with_extension("self");
+            let actual = file_size(eboot.as_std_path())?;
+
+            if actual > limit.0 {
+                // The stripped elf has no symbols left, so use the archived copy to find the largest crates
+                let elf = self
+                    .command
+                    .symbol_store
+                    .store()?
+                    .find_for_elf(&art.elf)?
+                    .unwrap_or_else(|| art.elf.clone());
+                let report = SizeReport::from_elf(&elf)?;
+
+                let (contributors_kind, contributors) = if report.crates.is_empty() {
+                    ("elf sections", report.sections)
+                } else {
+                    ("crates", report.crates)
+                };
+
+                violations.push(SizeLimitViolation {
+                    output: format!("eboot.bin of {}", art.package.name),
+                    limit: limit.0,
+                    actual,
+                    contributors_kind,
+                    contributors,
+                });
+            }
+        }
+
+        if !vpk {
+            return Ok(violations);
+        }
+
+        let assets = art
+            .assets()?
+            .into_iter()
+            .map(|(path, path_in_vpk)| {
+                Ok(SizeEntry {
+                    name: path_in_vpk.display().to_string(),
+                    size: file_size(&path)?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if let Some(limit) = limits.assets {
+            let actual = assets.iter().map(|a| a.size).sum();
+
+            if actual > limit.0 {
+                violations.push(SizeLimitViolation {
+                    output: format!("Assets of {}", art.package.name),
+                    limit: limit.0,
+                    actual,
+                    contributors_kind: "files",
+                    contributors: size::sorted(assets.iter().map(|a| (a.name.clone(), a.size))),
+                });
+            }
+        }
+
+        if let Some(limit) = limits.vpk {
+            let vpk = art.vpk_path();
+            let actual = file_size(vpk.as_std_path())?;
+
+            if actual > limit.0 {
+                let files = [
+                    (
+                        "eboot.bin".to_string(),
+                        file_size(art.elf.with_extension("self").as_std_path())?,
+                    ),
+                    (
+                        "sce_sys/param.sfo".to_string(),
+                        file_size(art.elf.with_extension("sfo").as_std_path())?,
+                    ),
+                ];
+
+                violations.push(SizeLimitViolation {
+                    output: vpk.to_string(),
+                    limit: limit.0,
+                    actual,
+                    contributors_kind: "files",
+                    contributors: size::sorted(
+                        files
+                            .into_iter()
+                            .chain(assets.into_iter().map(|a| (a.name, a.size))),
+                    ),
+                });
+            }
+        }
+
+        Ok(violations)
+    }
+
     #[allow(clippy::unused_self)]
     fn vpk_uploads(
         &self,
@@ -489,7 +979,7 @@ impl BuildContext<'_> {
         artifacts
             .iter()
             .map(|a| {
-                let src = a.elf.with_extension("vpk");
+                let src = a.vpk_path();
 
                 let separator = if destination.ends_with('/') { "" } else { "/" };
                 let dest = format!(
@@ -502,6 +992,28 @@ impl BuildContext<'_> {
             .collect::<anyhow::Result<Vec<_>>>()
     }
 
+    #[allow(clippy::unused_self)]
+    fn plugin_uploads(
+        &self,
+        artifacts: &[ExecutableArtifact],
+        destination: &str,
+    ) -> Vec<(Utf8PathBuf, String)> {
+        artifacts
+            .iter()
+            .map(|a| {
+                let src = a.plugin_path();
+
+                let separator = if destination.ends_with('/') { "" } else { "/" };
+                let dest = format!(
+                    "{destination}{separator}{}",
+                    src.file_name().unwrap_or_default()
+                );
+
+                (src, dest)
+            })
+            .collect()
+    }
+
     fn eboot_uploads(
         &self,
         artifacts: &[ExecutableArtifact],
@@ -545,6 +1057,101 @@ impl BuildContext<'_> {
     }
 }
 
+/// The `--message-format` cargo is called with.
+///
+/// Cargo is always called with a JSON format, so that compiler messages can be inspected,
+/// e.g. linker errors for missing stub libraries.
+struct MessageFormat {
+    /// The format value, e.g. `json` or `json-diagnostic-short`.
+    format: String,
+    /// If true, the user requested a JSON format, and cargo messages are forwarded to stdout unchanged.
+    forward: bool,
+    /// If true, diagnostics are rendered to stderr by cargo-vita instead of cargo.
+    render: bool,
+}
+
+impl MessageFormat {
+    /// Extracts `--message-format` from the cargo arguments, and returns the rest of the arguments.
+    fn from_cargo_args(cargo_args: &[String]) -> (Self, Vec<String>) {
+        let mut user_format = None;
+        let mut rest = Vec::with_capacity(cargo_args.len());
+        let mut args = cargo_args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                rest.push(arg.clone());
+                rest.extend(args.by_ref().cloned());
+            } else if let Some(format) = arg.strip_prefix("--message-format=") {
+                user_format = Some(format.to_string());
+            } else if arg == "--message-format" {
+                user_format = args.next().cloned();
+            } else {
+                rest.push(arg.clone());
+            }
+        }
+
+        let ansi = colored::control::SHOULD_COLORIZE.should_colorize();
+        let with_ansi = |format: &str| {
+            if ansi {
+                format!("{format},json-diagnostic-rendered-ansi")
+            } else {
+                format.to_string()
+            }
+        };
+
+        let format = match user_format {
+            // With `json-render-diagnostics` cargo would render the compiler messages
+            // instead of emitting them, so cargo-vita renders them in its place
+            Some(format) if format.split(',').any(|f| f == "json-render-diagnostics") => {
+                let parts = format
+                    .split(',')
+                    .filter(|f| *f != "json-render-diagnostics" && *f != "json")
+                    .collect::<Vec<_>>();
+                let format = if parts.is_empty() {
+                    "json".to_string()
+                } else {
+                    parts.join(",")
+                };
+
+                Self {
+                    format: if format.contains("json-diagnostic-rendered-ansi") {
+                        format
+                    } else {
+                        with_ansi(&format)
+                    },
+                    forward: true,
+                    render: true,
+                }
+            }
+            Some(format) if format.split(',').any(|f| f.starts_with("json")) => Self {
+                format,
+                forward: true,
+                render: false,
+            },
+            Some(format) if format.split(',').any(|f| f == "short") => Self {
+                format: with_ansi("json-diagnostic-short"),
+                forward: false,
+                render: true,
+            },
+            _ => Self {
+                format: if ansi {
+                    "json-diagnostic-rendered-ansi".to_string()
+                } else {
+                    "json".to_string()
+                },
+                forward: false,
+                render: true,
+            },
+        };
+
+        (format, rest)
+    }
+
+    fn cargo_arg(&self) -> String {
+        format!("--message-format={}", self.format)
+    }
+}
+
 fn upload(files: &[(Utf8PathBuf, String)], conn: &ConnectionArgs) -> anyhow::Result<()> {
     if files.is_empty() {
         return Ok(());
@@ -563,31 +1170,206 @@ fn upload(files: &[(Utf8PathBuf, String)], conn: &ConnectionArgs) -> anyhow::Res
     Ok(())
 }
 
-trait CommandExt {
-    fn pass_env<K, V>(&mut self, key: K, default: impl Fn() -> V) -> &mut Command
-    where
-        K: AsRef<str>,
-        V: AsRef<str>;
+/// Titles with the same id overwrite each other when installed.
+///
+/// Derived title ids that collide with another id of the build are moved to the next free id.
+/// Artifacts are visited in the order of their seeds, so that every artifact gets the same id
+/// regardless of the order cargo built them in. Title ids set in the metadata are kept, and
+/// their collisions are only reported. Every artifact is the cargo target name with its metadata.
+fn resolve_duplicate_title_ids(mut artifacts: Vec<(&str, &mut PackageMetadata)>) {
+    let mut taken = HashMap::new();
+
+    for (name, meta) in artifacts.iter().filter(|(_, m)| m.title_id_seed.is_none()) {
+        let Some(title_id) = &meta.title_id else {
+            continue;
+        };
 
-    fn pass_path_env<K, V>(&mut self, key: K, default: impl Fn() -> V) -> &mut Command
-    where
-        K: AsRef<str>,
-        V: AsRef<Path>,
-    {
-        self.pass_env(key, || default().as_ref().to_string_lossy().to_string())
+        if let Some(other) = taken.insert(title_id.to_string(), *name) {
+            warn!(
+                "{} {title_id} {} `{other}` and `{}`, {}",
+                "Title id".yellow(),
+                "is used by both".yellow(),
+                name,
+                "they will overwrite each other when installed".yellow(),
+            );
+        }
+    }
+
+    artifacts.retain(|(_, meta)| meta.title_id_seed.is_some());
+    artifacts.sort_by(|(_, a), (_, b)| a.title_id_seed.cmp(&b.title_id_seed));
+
+    for (name, meta) in artifacts {
+        let Some(mut title_id) = meta.title_id.clone() else {
+            continue;
+        };
+
+        while let Some(other) = taken.get(&*title_id) {
+            let next = title_id.next_derived();
+            info!(
+                "{} {title_id} {} `{other}`, {} {next} {} `{name}`",
+                "Title id".blue(),
+                "is already used by".blue(),
+                "using".blue(),
+                "for".blue(),
+            );
+            title_id = next;
+        }
+
+        taken.insert(title_id.to_string(), name);
+        meta.title_id = Some(title_id);
     }
 }
 
-impl CommandExt for Command {
-    fn pass_env<K, V>(&mut self, key: K, default: impl Fn() -> V) -> &mut Command
-    where
-        K: AsRef<str>,
-        V: AsRef<str>,
-    {
-        let key = key.as_ref();
-        match env::var(key) {
-            Ok(val) => self.env(key, val),
-            Err(_) => self.env(key, default().as_ref()),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message_format(args: &[&str]) -> (MessageFormat, Vec<String>) {
+        colored::control::set_override(false);
+        let args = args.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        MessageFormat::from_cargo_args(&args)
+    }
+
+    #[test]
+    fn default_message_format_is_rendered_by_cargo_vita() {
+        let (format, rest) = message_format(&["--release"]);
+
+        assert_eq!(format.cargo_arg(), "--message-format=json");
+        assert!(!format.forward);
+        assert!(format.render);
+        assert_eq!(rest, ["--release"]);
+    }
+
+    #[test]
+    fn json_message_format_is_forwarded() {
+        for args in [
+            ["--message-format=json"].as_slice(),
+            &["--message-format", "json"],
+        ] {
+            let (format, rest) = message_format(args);
+
+            assert_eq!(format.cargo_arg(), "--message-format=json");
+            assert!(format.forward);
+            assert!(!format.render);
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn diagnostics_rendered_by_cargo_are_rendered_by_cargo_vita() {
+        for (user_format, cargo_format) in [
+            ("json-render-diagnostics", "json"),
+            ("json,json-render-diagnostics", "json"),
+            (
+                "json-render-diagnostics,json-diagnostic-short",
+                "json-diagnostic-short",
+            ),
+        ] {
+            let (format, _) = message_format(&[&format!("--message-format={user_format}")]);
+
+            assert_eq!(
+                format.cargo_arg(),
+                format!("--message-format={cargo_format}")
+            );
+            assert!(format.forward);
+            assert!(format.render);
         }
     }
+
+    #[test]
+    fn short_message_format_keeps_diagnostics_short() {
+        let (format, _) = message_format(&["--message-format=short"]);
+
+        assert_eq!(format.cargo_arg(), "--message-format=json-diagnostic-short");
+        assert!(!format.forward);
+    }
+
+    #[test]
+    fn message_format_after_double_dash_is_not_extracted() {
+        let (format, rest) = message_format(&["--", "--message-format=json"]);
+
+        assert!(!format.forward);
+        assert_eq!(rest, ["--", "--message-format=json"]);
+    }
+
+    fn title_ids(artifacts: &[(&str, &str, Option<&str>)]) -> Vec<String> {
+        let mut metas = artifacts
+            .iter()
+            .map(|(_, title_id, seed)| {
+                let mut meta = serde_json::from_value::<PackageMetadata>(serde_json::json!({
+                    "title_id": title_id,
+                }))
+                .unwrap();
+                meta.title_id_seed = seed.map(ToString::to_string);
+                meta
+            })
+            .collect::<Vec<_>>();
+
+        resolve_duplicate_title_ids(
+            artifacts
+                .iter()
+                .map(|(name, _, _)| *name)
+                .zip(metas.iter_mut())
+                .collect(),
+        );
+
+        metas
+            .into_iter()
+            .map(|meta| meta.title_id.unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn colliding_derived_title_ids_are_probed_in_seed_order() {
+        assert_eq!(
+            title_ids(&[
+                ("b", "RUST00042", Some("app/test/b")),
+                ("a", "RUST00042", Some("app/test/a")),
+            ]),
+            ["RUST00043", "RUST00042"]
+        );
+        assert_eq!(
+            title_ids(&[
+                ("a", "RUST00042", Some("app/test/a")),
+                ("b", "RUST00042", Some("app/test/b")),
+            ]),
+            ["RUST00042", "RUST00043"]
+        );
+    }
+
+    #[test]
+    fn probing_skips_taken_title_ids() {
+        assert_eq!(
+            title_ids(&[
+                ("a", "RUST00042", Some("app/test/a")),
+                ("b", "RUST00042", Some("app/test/b")),
+                ("c", "RUST00043", Some("app/example/c")),
+            ]),
+            ["RUST00042", "RUST00044", "RUST00043"]
+        );
+    }
+
+    #[test]
+    fn explicit_title_ids_are_kept() {
+        assert_eq!(
+            title_ids(&[
+                ("it", "RUST00042", Some("app/test/it")),
+                ("app", "RUST00042", None),
+                ("other", "RUST00042", None),
+            ]),
+            ["RUST00043", "RUST00042", "RUST00042"]
+        );
+    }
+
+    #[test]
+    fn probing_wraps_around() {
+        assert_eq!(
+            title_ids(&[
+                ("a", "RUST99999", Some("app/test/a")),
+                ("b", "RUST99999", Some("app/test/b")),
+            ]),
+            ["RUST99999", "RUST00000"]
+        );
+    }
 }
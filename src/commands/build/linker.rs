@@ -0,0 +1,115 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use colored::Colorize;
+use log::warn;
+
+use crate::nids::NidDb;
+
+static UNDEFINED_REFERENCE: &str = "undefined reference to ";
+
+/// Extracts the symbol names from "undefined reference to" errors in the linker output.
+pub fn undefined_references(output: &str) -> impl Iterator<Item = &str> {
+    output
+        .match_indices(UNDEFINED_REFERENCE)
+        .filter_map(|(pos, _)| {
+            let rest = &output[pos + UNDEFINED_REFERENCE.len()..];
+            // Depending on the version, GNU ld quotes symbols either as `symbol' or 'symbol'
+            let rest = rest.strip_prefix(['`', '\''])?;
+            let end = rest.find('\'')?;
+
+            Some(&rest[..end])
+        })
+}
+
+/// Prints the stub libraries that provide the undefined `sce*` symbols, and how to link them.
+pub fn explain_undefined_references(symbols: &BTreeSet<String>, db_path: &Path) {
+    let symbols = symbols
+        .iter()
+        .filter(|s| s.starts_with("sce"))
+        .collect::<Vec<_>>();
+
+    if symbols.is_empty() {
+        return;
+    }
+
+    let dbs = match NidDb::load_all(db_path) {
+        Ok(dbs) => dbs,
+        Err(err) => {
+            warn!(
+                "{} {err:?}\n \
+                Set `{}` environment variable to the path of `db.yml` or a directory with NID database files.",
+                "Unable to load NID database to explain undefined references:".yellow(),
+                "VITA_NID_DB".cyan()
+            );
+            return;
+        }
+    };
+
+    let mut stubs = BTreeMap::<String, Vec<&str>>::new();
+    let mut unknown = Vec::new();
+
+    for symbol in symbols {
+        match dbs.iter().find_map(|db| db.find_function(symbol)) {
+            Some(function) => stubs
+                .entry(function.library.stub_name(function.library_name))
+                .or_default()
+                .push(symbol),
+            None => unknown.push(symbol.as_str()),
+        }
+    }
+
+    for (stub, symbols) in &stubs {
+        warn!(
+            "{} {}\n \
+            It provides {}.\n \
+            Pass `{}` to rustc, or add the following to your code:\n  \
+            {}\n  \
+            {}",
+            "Missing stub library".yellow(),
+            stub.cyan(),
+            symbols.join(", "),
+            format!("-l static={stub}").cyan(),
+            format!("#[link(name = \"{stub}\", kind = \"static\")]").cyan(),
+            "extern \"C\" {}".cyan()
+        );
+    }
+
+    if !unknown.is_empty() {
+        warn!(
+            "{} {}",
+            "Undefined references not found in the NID database:".yellow(),
+            unknown.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undefined_references_with_both_quote_styles() {
+        let output = "\
+            main.c:(.text+0x10): undefined reference to `sceKernelDelayThread'\n\
+            main.c:(.text+0x20): undefined reference to 'sceCtrlPeekBufferPositive'\n\
+            collect2: error: ld returned 1 exit status";
+
+        assert_eq!(
+            undefined_references(output).collect::<Vec<_>>(),
+            ["sceKernelDelayThread", "sceCtrlPeekBufferPositive"]
+        );
+    }
+
+    #[test]
+    fn undefined_references_ignore_other_errors() {
+        let output = "\
+            ld: cannot find -lSceFoo_stub\n\
+            undefined reference to sceUnquoted\n\
+            undefined reference to `sceUnterminated";
+
+        assert_eq!(undefined_references(output).count(), 0);
+    }
+}
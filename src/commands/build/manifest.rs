@@ -0,0 +1,36 @@
+use std::fs::{self, File};
+
+use anyhow::Context;
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::meta::FselfOptions;
+
+use super::size::SizeReport;
+
+/// Information about a built artifact, saved next to the elf file,
+/// so that the following builds can be compared against it.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BuildManifest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<SizeReport>,
+    /// The options the eboot was created with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fself: Option<FselfOptions>,
+}
+
+impl BuildManifest {
+    pub fn path(elf: &Utf8Path) -> Utf8PathBuf {
+        elf.with_extension("manifest.json")
+    }
+
+    pub fn load(path: &Utf8Path) -> anyhow::Result<Self> {
+        let file = File::open(path).context("Unable to open build manifest")?;
+        serde_json::from_reader(file).context("Unable to parse build manifest")
+    }
+
+    pub fn save(&self, path: &Utf8Path) -> anyhow::Result<()> {
+        let json = serde_json::to_vec_pretty(self).context("Unable to serialize build manifest")?;
+        fs::write(path, json).context("Unable to write build manifest")
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+};
+
+use anyhow::{bail, Context};
+use cargo_metadata::camino::Utf8PathBuf;
+use object::{Object, ObjectSymbol};
+
+use crate::meta::PluginExports;
+
+use super::ExecutableArtifact;
+
+/// The name field of `SceModuleInfo` is 27 bytes long, including the terminating zero.
+const MODULE_NAME_MAX_LEN: usize = 26;
+
+/// Returns the exports yml to pass to `vita-elf-create`. Unless the plugin metadata points
+/// to an existing file, the yml is generated from the metadata next to the elf.
+pub fn exports_file(art: &ExecutableArtifact) -> anyhow::Result<Utf8PathBuf> {
+    let plugin = &art.meta.plugin;
+
+    match (&plugin.exports_file, &plugin.exports) {
+        (Some(_), Some(_)) => bail!(
+            "`exports_file` and `exports` can't be used together in \
+            `[package.metadata.vita.plugin]` of {}",
+            art.package.name
+        ),
+        (Some(file), None) => Ok(art
+            .artifact
+            .manifest_path
+            .parent()
+            .context("Unable to get target manifest directory")?
+            .join(file)),
+        (None, exports) => {
+            let exports = exports.clone().unwrap_or_default();
+            let name = exports
+                .name
+                .clone()
+                .unwrap_or_else(|| art.package.name.replace('-', "_"));
+
+            let data = fs::read(&art.elf).context("Unable to read elf file")?;
+            let file = object::File::parse(&*data).context("Unable to parse elf file")?;
+            let defined = file
+                .symbols()
+                .filter(|s| s.is_definition() && s.is_global())
+                .filter_map(|s| s.name().ok())
+                .collect::<BTreeSet<_>>();
+
+            validate(&name, &exports, &defined)?;
+
+            let yaml = serde_yaml::to_string(&BTreeMap::from([(name, exports)]))
+                .context("Unable to serialize plugin exports")?;
+            let path = art.elf.with_extension("exports.yml");
+            fs::write(&path, yaml).with_context(|| format!("Unable to write {path}"))?;
+
+            Ok(path)
+        }
+    }
+}
+
+/// Checks that all names are valid C identifiers, and that every exported symbol is
+/// one of the global symbols defined in the elf.
+fn validate(name: &str, exports: &PluginExports, defined: &BTreeSet<&str>) -> anyhow::Result<()> {
+    let mut problems = Vec::new();
+
+    if !is_identifier(name) {
+        problems.push(format!("Module name `{name}` is not a valid identifier"));
+    }
+
+    if name.len() > MODULE_NAME_MAX_LEN {
+        problems.push(format!(
+            "Module name `{name}` is longer than {MODULE_NAME_MAX_LEN} characters"
+        ));
+    }
+
+    for function in [&exports.main.start, &exports.main.stop] {
+        if !defined.contains(function.as_str()) {
+            problems.push(format!(
+                "Module entry point `{function}` is not defined in the elf. \
+                Make sure it is a `#[no_mangle] extern \"C\"` function"
+            ));
+        }
+    }
+
+    let mut exported = BTreeSet::new();
+    let mut nids = BTreeMap::new();
+
+    for (library_name, library) in &exports.modules {
+        if !is_identifier(library_name) {
+            problems.push(format!(
+                "Library name `{library_name}` is not a valid identifier"
+            ));
+        }
+
+        if let Some(nid) = library.nid {
+            if let Some(other) = nids.insert(nid, library_name) {
+                problems.push(format!(
+                    "Libraries `{other}` and `{library_name}` have the same NID 0x{nid:08X}"
+                ));
+            }
+        }
+
+        for symbol in library.functions.iter().chain(&library.variables) {
+            if !is_identifier(symbol) {
+                problems.push(format!(
+                    "Exported symbol `{symbol}` of library `{library_name}` is not a valid identifier"
+                ));
+            } else if !defined.contains(symbol.as_str()) {
+                problems.push(format!(
+                    "Exported symbol `{symbol}` of library `{library_name}` is not defined in the elf. \
+                    Make sure it is `#[no_mangle]`"
+                ));
+            }
+
+            if !exported.insert(symbol) {
+                problems.push(format!("Symbol `{symbol}` is exported more than once"));
+            }
+        }
+    }
+
+    if !problems.is_empty() {
+        bail!("Invalid plugin exports:\n {}", problems.join("\n "));
+    }
+
+    Ok(())
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exports(yaml: &str) -> PluginExports {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn problems(name: &str, exports: &PluginExports, defined: &[&str]) -> Vec<String> {
+        let defined = defined.iter().copied().collect();
+
+        match validate(name, exports, &defined) {
+            Ok(()) => Vec::new(),
+            Err(err) => err
+                .to_string()
+                .lines()
+                .skip(1)
+                .map(|line| line.trim().to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn valid_exports() {
+        let exports = exports(
+            "
+            modules:
+              MyPluginLib:
+                nid: 0x12345678
+                functions: [my_function]
+                variables: [my_variable]
+            ",
+        );
+
+        assert_eq!(
+            problems(
+                "my_plugin",
+                &exports,
+                &["module_start", "module_stop", "my_function", "my_variable"]
+            ),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn missing_entry_points() {
+        assert_eq!(
+            problems("my_plugin", &PluginExports::default(), &["module_start"]),
+            [
+                "Module entry point `module_stop` is not defined in the elf. \
+            Make sure it is a `#[no_mangle] extern \"C\"` function"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_module_names() {
+        let defined = ["module_start", "module_stop"];
+
+        assert_eq!(
+            problems("my-plugin", &PluginExports::default(), &defined),
+            ["Module name `my-plugin` is not a valid identifier"]
+        );
+        assert_eq!(
+            problems(&"a".repeat(27), &PluginExports::default(), &defined),
+            [format!(
+                "Module name `{}` is longer than 26 characters",
+                "a".repeat(27)
+            )]
+        );
+    }
+
+    #[test]
+    fn invalid_exported_libraries() {
+        let exports = exports(
+            "
+            modules:
+              FirstLib:
+                nid: 1
+                functions: [shared, undefined]
+              Second-Lib:
+                nid: 1
+                functions: [shared, 1invalid]
+            ",
+        );
+
+        assert_eq!(
+            problems(
+                "my_plugin",
+                &exports,
+                &["module_start", "module_stop", "shared"]
+            ),
+            [
+                "Exported symbol `undefined` of library `FirstLib` is not defined in the elf. \
+                Make sure it is `#[no_mangle]`",
+                "Library name `Second-Lib` is not a valid identifier",
+                "Libraries `FirstLib` and `Second-Lib` have the same NID 0x00000001",
+                "Symbol `shared` is exported more than once",
+                "Exported symbol `1invalid` of library `Second-Lib` is not a valid identifier",
+            ]
+        );
+    }
+}
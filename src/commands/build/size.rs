@@ -0,0 +1,215 @@
+use std::{cmp::Ordering, collections::HashMap, fmt::Display, fs};
+
+use anyhow::Context;
+use cargo_metadata::camino::Utf8Path;
+use colored::Colorize;
+use log::info;
+use object::{Object, ObjectSection, ObjectSymbol, SectionFlags, SymbolKind};
+use serde::{Deserialize, Serialize};
+
+/// Symbols that do not demangle as Rust symbols are attributed to this pseudo-crate.
+static NON_RUST_CRATE: &str = "[non-rust]";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SizeEntry {
+    pub name: String,
+    pub size: u64,
+}
+
+/// Size breakdown of an elf file. All lists are sorted by size in descending order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SizeReport {
+    /// Total size of all sections that are loaded into memory.
+    pub total: u64,
+    pub sections: Vec<SizeEntry>,
+    pub crates: Vec<SizeEntry>,
+    pub symbols: Vec<SizeEntry>,
+}
+
+impl SizeReport {
+    pub fn from_elf(elf: &Utf8Path) -> anyhow::Result<Self> {
+        let data = fs::read(elf).context("Unable to read elf file")?;
+        let file = object::File::parse(&*data).context("Unable to parse elf file")?;
+
+        let sections = file
+            .sections()
+            .filter(|s| {
+                matches!(s.flags(), SectionFlags::Elf { sh_flags }
+                    if sh_flags & u64::from(object::elf::SHF_ALLOC) != 0)
+            })
+            .filter(|s| s.size() > 0)
+            .map(|s| {
+                Ok((
+                    s.name().context("Unable to read section name")?.to_string(),
+                    s.size(),
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let total = sections.iter().map(|(_, size)| size).sum();
+
+        let mut symbols = HashMap::new();
+        let mut crates = HashMap::new();
+
+        for symbol in file.symbols() {
+            if symbol.size() == 0
+                || symbol.section_index().is_none()
+                || !matches!(symbol.kind(), SymbolKind::Text | SymbolKind::Data)
+            {
+                continue;
+            }
+
+            let Ok(name) = symbol.name() else {
+                continue;
+            };
+
+            let (name, krate) = match rustc_demangle::try_demangle(name) {
+                Ok(demangled) => {
+                    let name = format!("{demangled:#}");
+                    let krate = crate_name(&name).to_string();
+                    (name, krate)
+                }
+                Err(_) => (name.to_string(), NON_RUST_CRATE.to_string()),
+            };
+
+            *symbols.entry(name).or_default() += symbol.size();
+            *crates.entry(krate).or_default() += symbol.size();
+        }
+
+        Ok(Self {
+            total,
+            sections: sorted(sections),
+            crates: sorted(crates),
+            symbols: sorted(symbols),
+        })
+    }
+
+    pub fn print(&self, previous: Option<&SizeReport>, top: usize) {
+        info!(
+            "{} {}{}",
+            "Total size:".blue(),
+            format_size(self.total),
+            previous
+                .map(|p| delta(self.total, p.total))
+                .unwrap_or_default()
+        );
+
+        print_entries(
+            "Sections",
+            &self.sections,
+            previous.map(|p| &p.sections[..]),
+            usize::MAX,
+        );
+        print_entries("Crates", &self.crates, previous.map(|p| &p.crates[..]), top);
+        print_entries(
+            "Symbols",
+            &self.symbols,
+            previous.map(|p| &p.symbols[..]),
+            top,
+        );
+    }
+}
+
+/// A build output that is larger than allowed by `package.metadata.vita.size_limits`.
+pub struct SizeLimitViolation {
+    pub output: String,
+    pub limit: u64,
+    pub actual: u64,
+    /// What the contributors are, e.g. "crates" or "files".
+    pub contributors_kind: &'static str,
+    pub contributors: Vec<SizeEntry>,
+}
+
+impl Display for SizeLimitViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} is {}, which exceeds the limit of {} by {}",
+            self.output,
+            format_size(self.actual),
+            format_size(self.limit),
+            format_size(self.actual.saturating_sub(self.limit)),
+        )?;
+
+        writeln!(f, "  Largest {}:", self.contributors_kind)?;
+        for entry in self.contributors.iter().take(10) {
+            writeln!(f, "  {:>10}  {}", format_size(entry.size), entry.name)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a size in bytes with a binary unit suffix, e.g. `1.5 MiB`.
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    #[allow(clippy::cast_precision_loss)]
+    let mut value = size as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Extracts the crate name from a demangled Rust symbol path.
+/// Trait implementations like `<alloc::vec::Vec<T> as core::ops::Drop>::drop`
+/// are attributed to the crate of the implementing type.
+fn crate_name(path: &str) -> &str {
+    let path = path.trim_start_matches('<');
+    let end = path
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(path.len());
+
+    &path[..end]
+}
+
+pub fn sorted(entries: impl IntoIterator<Item = (String, u64)>) -> Vec<SizeEntry> {
+    let mut entries = entries
+        .into_iter()
+        .map(|(name, size)| SizeEntry { name, size })
+        .collect::<Vec<_>>();
+
+    entries.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+    entries
+}
+
+fn delta(current: u64, previous: u64) -> String {
+    match current.cmp(&previous) {
+        Ordering::Greater => format!(" (+{})", format_size(current - previous))
+            .red()
+            .to_string(),
+        Ordering::Less => format!(" (-{})", format_size(previous - current))
+            .green()
+            .to_string(),
+        Ordering::Equal => String::new(),
+    }
+}
+
+fn print_entries(title: &str, entries: &[SizeEntry], previous: Option<&[SizeEntry]>, top: usize) {
+    info!("{}", format!("{title}:").blue());
+
+    for entry in entries.iter().take(top) {
+        let change = match previous {
+            Some(previous) => match previous.iter().find(|p| p.name == entry.name) {
+                Some(p) => delta(entry.size, p.size),
+                None => " (new)".red().to_string(),
+            },
+            None => String::new(),
+        };
+
+        info!("  {:>10}  {}{change}", format_size(entry.size), entry.name);
+    }
+
+    if entries.len() > top {
+        info!("  ... and {} more", entries.len() - top);
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    fs,
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
+use colored::Colorize;
+use log::info;
+
+use crate::{meta::ImportsMetadata, nids::NidDb, sdk::VitaSdk};
+
+/// Merges the imported modules of the package, from the files and the inline metadata.
+pub fn imports_db(imports: &ImportsMetadata, crate_dir: &Utf8Path) -> anyhow::Result<NidDb> {
+    let mut db = NidDb::default();
+
+    for file in &imports.files {
+        db.modules
+            .extend(NidDb::load(crate_dir.join(file).as_std_path())?.modules);
+    }
+
+    db.modules.extend(imports.modules.clone());
+
+    Ok(db)
+}
+
+/// Generated stub libraries.
+pub struct Stubs {
+    /// A linker response file with the flags to link all of the stub libraries.
+    /// Its path does not depend on the imports, so that changing them does not change rustflags,
+    /// which would rebuild every crate including std.
+    pub link_args: Utf8PathBuf,
+    /// Whether the stubs were regenerated, and the binaries need to be linked again.
+    pub changed: bool,
+}
+
+/// Generates stub libraries for the imported modules into `out_dir` with `vita-libs-gen`.
+/// The stubs are only regenerated when the imports change.
+pub fn generate(
+    sdk: &VitaSdk,
+    imports: &ImportsMetadata,
+    crate_dir: &Utf8Path,
+    out_dir: &Utf8Path,
+) -> anyhow::Result<Stubs> {
+    let db = imports_db(imports, crate_dir)?;
+
+    let stubs = db
+        .libraries()
+        .map(|(name, library)| library.stub_name(name))
+        .collect::<Vec<_>>();
+
+    let yaml = serde_yaml::to_string(&db).context("Unable to serialize imported modules")?;
+    let yml_path = out_dir.join("imports.yml");
+    let link_args = out_dir.join("link.args");
+
+    let up_to_date = fs::read_to_string(&yml_path).is_ok_and(|existing| existing == yaml)
+        && link_args.exists()
+        && stubs
+            .iter()
+            .all(|stub| out_dir.join(format!("lib{stub}.a")).exists());
+
+    if up_to_date {
+        info!("{}", "Stub libraries are up to date".yellow());
+        return Ok(Stubs {
+            link_args,
+            changed: false,
+        });
+    }
+
+    fs::create_dir_all(out_dir).with_context(|| format!("Unable to create {out_dir}"))?;
+    fs::write(&yml_path, yaml).with_context(|| format!("Unable to write {yml_path}"))?;
+
+    let mut command = Command::new(sdk.binary("vita-libs-gen"));
+    command
+        .arg(&yml_path)
+        .arg(out_dir)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+
+    info!("{}: {command:?}", "Generating stubs".blue());
+
+    if !command.status()?.success() {
+        bail!("vita-libs-gen failed");
+    }
+
+    // vita-libs-gen generates the sources and a Makefile that builds them with the SDK toolchain
+    let mut command = Command::new("make");
+    sdk.set_path(&mut command);
+    command
+        .arg("-C")
+        .arg(out_dir)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+
+    info!("{}: {command:?}", "Building stubs".blue());
+
+    if !command.status()?.success() {
+        bail!("Building stub libraries failed");
+    }
+
+    let args = [format!("-L{out_dir}")]
+        .into_iter()
+        .chain(stubs.iter().map(|stub| format!("-l{stub}")))
+        .map(|arg| quote_response_file_arg(&arg))
+        .collect::<Vec<_>>()
+        .join("\n");
+    fs::write(&link_args, args + "\n").with_context(|| format!("Unable to write {link_args}"))?;
+
+    Ok(Stubs {
+        link_args,
+        changed: true,
+    })
+}
+
+/// gcc splits response files on whitespace, unless it is quoted.
+fn quote_response_file_arg(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', r"\\").replace('"', r#"\""#))
+}
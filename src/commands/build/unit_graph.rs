@@ -1,18 +1,158 @@
-use std::process::{Command, Stdio};
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    process::{Command, Stdio},
+};
 
 use anyhow::Context;
+use colored::Colorize;
 
-pub struct BuildHints {
-    // Can be "dev" or "release", or any custom profile
-    pub profile: String,
+use crate::meta::VITA_TARGET;
+
+/// Crates (and optionally their features) that depend on std features not supported on Vita.
+static UNSUPPORTED_CRATE_FEATURES: &[(&str, Option<&str>, &str)] = &[
+    (
+        "tokio",
+        Some("process"),
+        "Spawning processes is not supported on Vita.",
+    ),
+    (
+        "tokio",
+        Some("signal"),
+        "Unix signals are not supported on Vita.",
+    ),
+    (
+        "libloading",
+        None,
+        "Loading dynamic libraries is not supported on Vita.",
+    ),
+    ("ctrlc", None, "Unix signals are not supported on Vita."),
+    (
+        "signal-hook",
+        None,
+        "Unix signals are not supported on Vita.",
+    ),
+];
+
+/// A potential problem found in the unit graph before the build starts.
+pub struct Diagnostic {
+    pub title: String,
+    pub help: String,
+}
 
-    // Can be "None", "debuginfo", "symbols", "true" or any invalid value
-    strip: Option<String>,
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n {}", self.title.yellow(), self.help)
+    }
+}
+
+pub struct BuildHints {
+    units: Vec<Unit>,
 }
 
 impl BuildHints {
-    pub fn strip_symbols(&self) -> bool {
-        [Some("symbols"), Some("true")].contains(&self.strip.as_deref())
+    /// Analyzes all units built for the Vita target, and returns the problems
+    /// that would either fail the build or produce a broken binary.
+    pub fn diagnostics(&self, build_std: &str) -> Vec<Diagnostic> {
+        let vita_units = self
+            .units
+            .iter()
+            .filter(|u| u.platform.as_deref() == Some(VITA_TARGET))
+            .collect::<Vec<_>>();
+
+        let profiles = vita_units.iter().map(|u| &u.profile).fold(
+            Vec::<&Profile>::new(),
+            |mut profiles, p| {
+                if !profiles.iter().any(|existing| existing.name == p.name) {
+                    profiles.push(p);
+                }
+                profiles
+            },
+        );
+
+        let builds_panic_unwind = build_std.split(',').any(|c| c.trim() == "panic_unwind");
+        let mut diagnostics = Vec::new();
+
+        for profile in profiles {
+            if profile.strip_symbols() {
+                diagnostics.push(strip_symbols_diagnostic(&profile.name));
+            }
+
+            if profile.panic.as_deref() == Some("abort") && builds_panic_unwind {
+                diagnostics.push(Diagnostic {
+                    title: format!(
+                        "Profile `{}` sets `panic = \"abort\"`, but `build_std` includes `panic_unwind`.",
+                        profile.name
+                    ),
+                    help: format!(
+                        "Set `{}` in `{}` section of your Cargo.toml.",
+                        "build_std = \"std,panic_abort\"".cyan(),
+                        "[package.metadata.vita]".cyan()
+                    ),
+                });
+            }
+        }
+
+        let mut unsupported = BTreeSet::new();
+
+        for unit in vita_units.iter().filter(|u| !u.is_std) {
+            for (name, feature, reason) in UNSUPPORTED_CRATE_FEATURES {
+                let name_matches = unit.target.name == name.replace('-', "_");
+                let feature_matches =
+                    feature.is_none_or(|f| unit.features.iter().any(|uf| uf == f));
+
+                if name_matches && feature_matches {
+                    unsupported.insert((*name, *feature, *reason));
+                }
+            }
+        }
+
+        for (name, feature, reason) in unsupported {
+            let title = match feature {
+                Some(feature) => format!("Crate `{name}` is built with `{feature}` feature."),
+                None => format!("Crate `{name}` is a dependency."),
+            };
+
+            diagnostics.push(Diagnostic {
+                title,
+                help: reason.to_string(),
+            });
+        }
+
+        diagnostics
+    }
+
+    /// The profiles of the units built for the Vita target, that strip symbols from the elf.
+    pub fn strip_symbols_diagnostics(&self) -> Vec<Diagnostic> {
+        self.units
+            .iter()
+            .filter(|u| u.platform.as_deref() == Some(VITA_TARGET) && u.profile.strip_symbols())
+            .map(|u| u.profile.name.as_str())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(strip_symbols_diagnostic)
+            .collect()
+    }
+}
+
+fn strip_symbols_diagnostic(profile: &str) -> Diagnostic {
+    Diagnostic {
+        title: format!("Profile `{profile}` strips symbols from ELF, which is unsupported."),
+        help: format!(
+            "Symbols in elf are required by `{velf}` to create a velf file.\n \
+            Please remove `{strip_true}` or `{strip_symbols}` from your Cargo.toml.\n \
+            If you want to optimize for the binary size, replace it \
+            with `{strip_debug}` to strip debug section.\n \
+            If you want to strip the symbol data from the resulting \
+            binary, set `{strip_velf}` in `{vita_section}` \
+            section of your Cargo.toml, this would strip the symbols from the velf.",
+            velf = "vita-elf-create".cyan(),
+            strip_true = "strip=true".cyan(),
+            strip_symbols = "strip=\"symbols\"".cyan(),
+            strip_debug = "strip=\"debuginfo\"".cyan(),
+            strip_velf = "strip_symbols = true".cyan(),
+            vita_section = format!("[package.metadata.vita.profile.{profile}]").cyan()
+        ),
     }
 }
 
@@ -23,15 +163,42 @@ struct UnitGraph {
 
 #[derive(serde::Deserialize)]
 struct Unit {
+    target: Target,
     profile: Profile,
+    platform: Option<String>,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default)]
+    is_std: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct Target {
+    name: String,
 }
 
 #[derive(serde::Deserialize)]
 struct Profile {
+    // Can be "dev" or "release", or any custom profile
     name: String,
     strip: Strip,
+    // Can be "unwind" or "abort"
+    panic: Option<String>,
+}
+
+impl Profile {
+    fn strip_symbols(&self) -> bool {
+        let strip = self
+            .strip
+            .resolved
+            .as_ref()
+            .and_then(|s| s.named.as_deref());
+
+        [Some("symbols"), Some("true")].contains(&strip)
+    }
 }
 
+// Can be "None", "debuginfo", "symbols", "true" or any invalid value
 #[derive(serde::Deserialize)]
 struct Strip {
     resolved: Option<StripResolved>,
@@ -56,15 +223,204 @@ pub fn try_parse_unit_graph(mut command: Command) -> anyhow::Result<BuildHints>
         .stdout;
     let json = serde_json::from_slice::<UnitGraph>(&stdout).context("Unable to parse json")?;
 
-    let last_unit = json
-        .units
-        .into_iter()
-        .next_back()
-        .context("No units found")?
-        .profile;
-
-    Ok(BuildHints {
-        profile: last_unit.name,
-        strip: last_unit.strip.resolved.and_then(|s| s.named),
-    })
+    Ok(BuildHints { units: json.units })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn unit(name: &str, profile: &str, features: &[&str], platform: Option<&str>) -> Value {
+        json!({
+            "target": { "name": name },
+            "profile": {
+                "name": profile,
+                "strip": { "resolved": { "Named": "debuginfo" } },
+                "panic": "unwind",
+            },
+            "platform": platform,
+            "features": features,
+            "is_std": false,
+        })
+    }
+
+    fn vita_unit(name: &str, profile: &str, features: &[&str]) -> Value {
+        unit(name, profile, features, Some(VITA_TARGET))
+    }
+
+    fn with_profile(mut unit: Value, key: &str, value: Value) -> Value {
+        unit["profile"][key] = value;
+        unit
+    }
+
+    type Case<'a> = (&'a str, Vec<Value>, &'a str, &'a [&'a str]);
+
+    fn check(cases: &[Case]) {
+        for (name, units, build_std, expected) in cases {
+            let graph = serde_json::from_value::<UnitGraph>(json!({ "units": units })).unwrap();
+            let titles = BuildHints { units: graph.units }
+                .diagnostics(build_std)
+                .into_iter()
+                .map(|d| d.title)
+                .collect::<Vec<_>>();
+
+            assert_eq!(titles, *expected, "case: {name}");
+        }
+    }
+
+    fn strip(strip: &str) -> Value {
+        json!({ "resolved": { "Named": strip } })
+    }
+
+    #[test]
+    fn strip_diagnostics() {
+        let stripped = "Profile `release` strips symbols from ELF, which is unsupported.";
+
+        check(&[
+            (
+                "no problems",
+                vec![vita_unit("app", "dev", &[])],
+                "std",
+                &[],
+            ),
+            (
+                "strip symbols",
+                vec![with_profile(
+                    vita_unit("app", "release", &[]),
+                    "strip",
+                    strip("symbols"),
+                )],
+                "std",
+                &[stripped],
+            ),
+            (
+                "strip true",
+                vec![with_profile(
+                    vita_unit("app", "release", &[]),
+                    "strip",
+                    strip("true"),
+                )],
+                "std",
+                &[stripped],
+            ),
+            (
+                "strip debuginfo",
+                vec![with_profile(
+                    vita_unit("app", "release", &[]),
+                    "strip",
+                    strip("debuginfo"),
+                )],
+                "std",
+                &[],
+            ),
+            (
+                "strip of a host unit",
+                vec![with_profile(
+                    unit("build_script_build", "release", &[], None),
+                    "strip",
+                    strip("symbols"),
+                )],
+                "std",
+                &[],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn panic_diagnostics() {
+        let abort =
+            "Profile `dev` sets `panic = \"abort\"`, but `build_std` includes `panic_unwind`.";
+
+        check(&[
+            (
+                "panic abort with panic_unwind",
+                vec![with_profile(
+                    vita_unit("app", "dev", &[]),
+                    "panic",
+                    json!("abort"),
+                )],
+                "std, panic_unwind",
+                &[abort],
+            ),
+            (
+                "panic abort with panic_abort",
+                vec![with_profile(
+                    vita_unit("app", "dev", &[]),
+                    "panic",
+                    json!("abort"),
+                )],
+                "std,panic_abort",
+                &[],
+            ),
+            (
+                "panic unwind with panic_unwind",
+                vec![vita_unit("app", "dev", &[])],
+                "std,panic_unwind",
+                &[],
+            ),
+            (
+                "every profile is reported once",
+                vec![
+                    with_profile(vita_unit("app", "dev", &[]), "panic", json!("abort")),
+                    with_profile(vita_unit("dep", "dev", &[]), "panic", json!("abort")),
+                ],
+                "std,panic_unwind",
+                &[abort],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn unsupported_crate_diagnostics() {
+        let mut std_unit = vita_unit("libloading", "dev", &[]);
+        std_unit["is_std"] = json!(true);
+
+        check(&[
+            (
+                "unsupported feature",
+                vec![vita_unit("tokio", "dev", &["rt", "process"])],
+                "std",
+                &["Crate `tokio` is built with `process` feature."],
+            ),
+            (
+                "supported features of a crate with unsupported ones",
+                vec![vita_unit("tokio", "dev", &["rt", "net"])],
+                "std",
+                &[],
+            ),
+            (
+                "every unsupported feature",
+                vec![vita_unit("tokio", "dev", &["process", "signal"])],
+                "std",
+                &[
+                    "Crate `tokio` is built with `process` feature.",
+                    "Crate `tokio` is built with `signal` feature.",
+                ],
+            ),
+            (
+                "unsupported crate with a dashed name",
+                vec![vita_unit("signal_hook", "dev", &[])],
+                "std",
+                &["Crate `signal-hook` is a dependency."],
+            ),
+            (
+                "unsupported crate reported once",
+                vec![
+                    vita_unit("ctrlc", "dev", &[]),
+                    vita_unit("ctrlc", "release", &[]),
+                ],
+                "std",
+                &["Crate `ctrlc` is a dependency."],
+            ),
+            (
+                "unsupported crate built for the host",
+                vec![unit("libloading", "dev", &[], None)],
+                "std",
+                &[],
+            ),
+            ("unsupported std unit", vec![std_unit], "std", &[]),
+        ]);
+    }
 }
@@ -0,0 +1,73 @@
+use std::{
+    env,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use clap::Args;
+use colored::Colorize;
+use log::{info, warn};
+
+use crate::sdk::{VitaSdk, BINDGEN_CLANG_ARGS_ENV};
+
+use super::Executor;
+
+#[derive(Args, Debug)]
+pub struct CheckClang {
+    /// The header to parse, relative to the SDK include directory.
+    #[arg(long, default_value = "psp2/kernel/processmgr.h")]
+    header: String,
+}
+
+impl Executor for CheckClang {
+    fn execute(&self) -> anyhow::Result<()> {
+        let sdk = VitaSdk::from_env()?;
+
+        if env::var_os(BINDGEN_CLANG_ARGS_ENV).is_some() {
+            warn!(
+                "{} {}",
+                BINDGEN_CLANG_ARGS_ENV.cyan(),
+                "is set and overrides the default arguments, \
+                but the check is done with the default arguments"
+                    .yellow()
+            );
+        }
+
+        // The same variable is used by bindgen to find clang
+        let clang = env::var("CLANG_PATH").unwrap_or_else(|_| "clang".to_string());
+
+        let mut command = Command::new(clang);
+        command
+            .args(sdk.bindgen_clang_args())
+            .args(["-fsyntax-only", "-x", "c", "-"])
+            .stdin(Stdio::piped());
+
+        info!("{}: {command:?}", "Parsing SDK header with clang".blue());
+
+        let mut process = command.spawn().context(
+            "Unable to run clang. Install clang, or set CLANG_PATH environment variable to its path",
+        )?;
+
+        process
+            .stdin
+            .take()
+            .context("clang has no stdin")?
+            .write_all(format!("#include <{}>\n", self.header).as_bytes())
+            .context("Unable to write to clang stdin")?;
+
+        if !process.wait()?.success() {
+            bail!(
+                "clang failed to parse `{}`. bindgen will not be able to generate bindings to the SDK headers",
+                self.header
+            );
+        }
+
+        info!(
+            "{}",
+            format!("clang parsed `{}`, bindgen is ready to use", self.header).green()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+use cargo_metadata::camino::Utf8PathBuf;
+use clap::Args;
+use colored::Colorize;
+use log::{info, warn};
+use walkdir::WalkDir;
+
+use crate::{
+    imports,
+    meta::{parse_crate_metadata, VITA_TARGET},
+    nids::{self, NidDb},
+    sdk::VitaSdk,
+};
+
+use super::Executor;
+
+#[derive(Args, Debug)]
+pub struct CheckImports {
+    /// Firmware versions to check the imports against, e.g. `3.60`.
+    #[arg(long, short = 'f', required = true)]
+    firmware: Vec<String>,
+
+    /// A NID database file in `db.yml` format, or a directory with such files,
+    /// with the `firmware` field set. Defaults to the database of `vita-headers`.
+    #[arg(long, env = "VITA_NID_DB")]
+    db: Option<PathBuf>,
+
+    /// Elf files to check. Defaults to all elf files built for the Vita target.
+    elf: Vec<Utf8PathBuf>,
+}
+
+impl Executor for CheckImports {
+    fn execute(&self) -> anyhow::Result<()> {
+        let db_path = match &self.db {
+            Some(db) => db.clone(),
+            None => nids::default_db_path(VitaSdk::from_env()?.root()),
+        };
+        let dbs = NidDb::load_all(&db_path)?;
+
+        let elfs = if self.elf.is_empty() {
+            built_elfs()?
+        } else {
+            self.elf.clone()
+        };
+
+        if elfs.is_empty() {
+            bail!("No elf files found, build the project first or pass the elf files to check");
+        }
+
+        let mut failed = false;
+
+        for elf in &elfs {
+            let mut imports = imports::read_imports(elf)
+                .with_context(|| format!("Unable to read imports of {elf}"))?;
+
+            for import in &mut imports {
+                import.resolve_names(&dbs);
+            }
+
+            for firmware in &self.firmware {
+                let firmware_dbs = imports::firmware_dbs(&dbs, firmware);
+
+                if firmware_dbs.is_empty() {
+                    bail!(
+                        "No NID database for firmware {firmware} found in {}",
+                        db_path.display()
+                    );
+                }
+
+                let unresolved = imports::unresolved_imports(&imports, &dbs, &firmware_dbs);
+
+                if unresolved.is_empty() {
+                    info!(
+                        "{} {elf} {}",
+                        "All imports of".blue(),
+                        format!("resolve on firmware {firmware}").blue()
+                    );
+                    continue;
+                }
+
+                failed = true;
+                warn!(
+                    "{} {elf} {}",
+                    "Imports of".yellow(),
+                    format!("that would not resolve on firmware {firmware}:").yellow()
+                );
+
+                for (import, reason) in unresolved {
+                    warn!(" {} {import}: {reason}", import.kind);
+                }
+            }
+        }
+
+        if failed {
+            bail!("Some imports would not resolve on the target firmware");
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the elf files of all profiles in the target directory.
+fn built_elfs() -> anyhow::Result<Vec<Utf8PathBuf>> {
+    let (_, _, target_directory) = parse_crate_metadata()?;
+
+    Ok(WalkDir::new(target_directory.join(VITA_TARGET))
+        .max_depth(3)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.file_name() != "deps" && e.file_name() != "build")
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "elf"))
+        .filter_map(|e| Utf8PathBuf::from_path_buf(e.into_path()).ok())
+        .collect())
+}
@@ -1,19 +1,21 @@
 use std::{
-    io,
+    fs, io,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
 use anyhow::{bail, Context};
 use clap::{Args, Subcommand};
 use colored::Colorize;
-use log::{info, warn};
+use log::{debug, info, warn};
 use suppaftp::FtpError;
-use tempfile::NamedTempFile;
+use tempfile::{NamedTempFile, TempPath};
 
-use super::{ConnectionArgs, Executor};
+use super::{ConnectionArgs, Executor, OptionalConnectionArgs, SymbolStoreArgs};
 use crate::{
     ftp,
     meta::{parse_crate_metadata, VITA_TARGET},
+    module_info::ModuleFingerprint,
 };
 
 #[derive(Args, Debug)]
@@ -24,7 +26,8 @@ pub struct Coredump {
 
 #[derive(Subcommand, Debug)]
 pub enum CoredumpCmd {
-    /// Downloads the latest coredump from Vita and uses vita-parse-core tool to parse it against the elf file of the current project.
+    /// Downloads the latest coredump from Vita (or takes a local one) and uses vita-parse-core tool
+    /// to parse it against the elf file the crashed module was built from.
     Parse(Parse),
     /// Deletes all coredump files from Vita
     Clean(Clean),
@@ -32,17 +35,24 @@ pub enum CoredumpCmd {
 
 #[derive(Args, Debug)]
 pub struct Parse {
-    /// A path to the ELF file. If not provided the tool will try to guess it.
+    /// A local coredump file to parse, e.g. one sent by a tester. If not provided,
+    /// the latest coredump is downloaded from the Vita.
+    #[arg(long)]
+    core: Option<PathBuf>,
+    /// A path to the ELF file. If not provided the tool will look it up in the symbol store
+    /// by the modules listed in the coredump, and if that fails, will try to guess it.
     #[arg(long)]
     elf: Option<String>,
-    /// If ELF file is not explicitly provided, will use the artifact from this profile.
+    /// If ELF file is neither explicitly provided nor found in the symbol store, will use the artifact from this profile.
     #[arg(long, short = 'p', default_value = "debug")]
     profile: String,
     /// If true, will save coredump to tmp. Otherwise coredump is not saved to disk.
     #[arg(long, short = 's', default_value = "false")]
     persist: bool,
     #[command(flatten)]
-    connection: ConnectionArgs,
+    symbol_store: SymbolStoreArgs,
+    #[command(flatten)]
+    connection: OptionalConnectionArgs,
 }
 
 #[derive(Args, Debug)]
@@ -55,82 +65,42 @@ impl Executor for Coredump {
     fn execute(&self) -> anyhow::Result<()> {
         match &self.cmd {
             CoredumpCmd::Parse(args) => {
-                let mut ftp = ftp::connect(&args.connection)?;
+                let (path, _tmp_file) = if let Some(core) = &args.core {
+                    (core.clone(), None)
+                } else if let Some(downloaded) = download_coredump(args)? {
+                    downloaded
+                } else {
+                    warn!("{}", "No coredump files found.".yellow());
+                    return Ok(());
+                };
 
-                ftp.cwd("ux0:/data/")
-                    .context("Unable to cwd to ux0:/data/")?;
-                let files = ftp.list(None).context("Unable to list files in cwd")?;
+                let elf = find_elf(args, &path)?;
+
+                let mut command = Command::new("vita-parse-core");
+
+                command
+                    .arg(&path)
+                    .arg(&elf)
+                    .stdin(Stdio::inherit())
+                    .stdout(Stdio::inherit())
+                    .stderr(Stdio::inherit());
 
-                if let Some(coredump) = find_core_dumps(&files).max() {
-                    info!("{}: {coredump}", "Downloading file".blue());
-                    let mut reader = ftp
-                        .retr_as_buffer(coredump)
-                        .context("Unable to download coredump")?;
-
-                    let mut tmp_file =
-                        NamedTempFile::new().context("Unable to create temporary file")?;
-
-                    io::copy(&mut reader, &mut tmp_file)
-                        .context("Unable to write coredump to file")?;
-
-                    let tmp_file = tmp_file.into_temp_path();
-
-                    let path = if args.persist {
-                        let file = tmp_file
-                            .parent()
-                            .context("Unable to get parent directory")?
-                            .join(coredump);
-
-                        tmp_file
-                            .persist(&file)
-                            .context("Unable to persist coredump")?;
-                        file
-                    } else {
-                        tmp_file.to_path_buf()
-                    };
-
-                    let elf = if let Some(elf) = &args.elf {
-                        elf.clone()
-                    } else {
-                        let (_, pkg, target_directory) = parse_crate_metadata(None)?;
-                        let pkg = pkg.context("Not in a crate")?;
-
-                        target_directory
-                            .join(VITA_TARGET)
-                            .join(&args.profile)
-                            .join(pkg.name)
-                            .with_extension("elf")
-                            .to_string()
-                    };
-
-                    let mut command = Command::new("vita-parse-core");
-
-                    command
-                        .arg(&path)
-                        .arg(&elf)
-                        .stdin(Stdio::inherit())
-                        .stdout(Stdio::inherit())
-                        .stderr(Stdio::inherit());
-
-                    info!("{}: {command:?}", "Parsing coredump".blue());
-
-                    let status = command.status();
-
-                    if let Err(err) = &status {
-                        if err.kind() == io::ErrorKind::NotFound {
-                            bail!(
+                info!("{}: {command:?}", "Parsing coredump".blue());
+
+                let status = command.status();
+
+                if let Err(err) = &status {
+                    if err.kind() == io::ErrorKind::NotFound {
+                        bail!(
                                 "`vita-parse-core` not found. \
                                 Ensure this tool is installed from https://github.com/xyzz/vita-parse-core \
                                 and is available in your PATH."
                             );
-                        }
                     }
+                }
 
-                    if !status?.success() {
-                        bail!("vita-parse-core failed");
-                    }
-                } else {
-                    warn!("{}", "No coredump files found.".yellow());
+                if !status?.success() {
+                    bail!("vita-parse-core failed");
                 }
             }
             CoredumpCmd::Clean(args) => {
@@ -163,6 +133,91 @@ impl Executor for Coredump {
     }
 }
 
+/// Downloads the latest coredump from the Vita. Unless it is persisted, the file is removed
+/// when the returned temporary path is dropped.
+fn download_coredump(args: &Parse) -> anyhow::Result<Option<(PathBuf, Option<TempPath>)>> {
+    let mut ftp = ftp::connect(&args.connection.clone().required()?)?;
+
+    ftp.cwd("ux0:/data/")
+        .context("Unable to cwd to ux0:/data/")?;
+    let files = ftp.list(None).context("Unable to list files in cwd")?;
+
+    let Some(coredump) = find_core_dumps(&files).max() else {
+        return Ok(None);
+    };
+
+    info!("{}: {coredump}", "Downloading file".blue());
+    let mut reader = ftp
+        .retr_as_buffer(coredump)
+        .context("Unable to download coredump")?;
+
+    let mut tmp_file = NamedTempFile::new().context("Unable to create temporary file")?;
+
+    io::copy(&mut reader, &mut tmp_file).context("Unable to write coredump to file")?;
+
+    let tmp_file = tmp_file.into_temp_path();
+
+    if args.persist {
+        let file = tmp_file
+            .parent()
+            .context("Unable to get parent directory")?
+            .join(coredump);
+
+        tmp_file
+            .persist(&file)
+            .context("Unable to persist coredump")?;
+
+        return Ok(Some((file, None)));
+    }
+
+    Ok(Some((tmp_file.to_path_buf(), Some(tmp_file))))
+}
+
+fn find_elf(args: &Parse, coredump: &Path) -> anyhow::Result<String> {
+    if let Some(elf) = &args.elf {
+        return Ok(elf.clone());
+    }
+
+    let data = fs::read(coredump).context("Unable to read coredump")?;
+
+    match ModuleFingerprint::from_coredump(&data) {
+        Ok(modules) => {
+            for module in &modules {
+                debug!("{} {module}", "Module in coredump:".blue());
+            }
+
+            let archived = args.symbol_store.store()?.find_for_modules(&modules);
+
+            if let Some((module, elf)) = archived {
+                info!(
+                    "{} {module}: {elf}",
+                    "Found elf of module in symbol store".blue()
+                );
+                return Ok(elf.to_string());
+            }
+        }
+        Err(err) => warn!(
+            "{} {err:#}",
+            "Unable to read modules from coredump:".yellow()
+        ),
+    }
+
+    warn!(
+        "{}",
+        "No matching elf found in symbol store, guessing the elf path".yellow()
+    );
+
+    let (_, pkg, target_directory) = parse_crate_metadata()?;
+    let pkg = pkg.context("Not in a crate")?;
+
+    Ok(target_directory
+        .join(VITA_TARGET)
+        .join(&args.profile)
+        .join(pkg.name)
+        .with_extension("elf")
+        .to_string())
+}
+
 fn find_core_dumps(files: &[String]) -> impl Iterator<Item = &str> {
     files
         .iter()
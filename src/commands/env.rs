@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+
+use crate::{
+    meta::{parse_crate_metadata, VITA_TARGET},
+    sdk::VitaSdk,
+};
+
+use super::Executor;
+
+#[derive(Args, Debug)]
+pub struct Env {
+    /// The syntax the environment is printed in.
+    #[arg(long, short = 'f', value_enum, default_value_t = EnvFormat::Bash)]
+    format: EnvFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum EnvFormat {
+    Bash,
+    Fish,
+    Powershell,
+    Json,
+}
+
+impl Executor for Env {
+    fn execute(&self) -> anyhow::Result<()> {
+        let (meta, _, _) = parse_crate_metadata()?;
+        let command = VitaSdk::from_env()?.cargo("build", &meta.build_std);
+
+        let mut vars = command
+            .get_envs()
+            .filter_map(|(key, value)| {
+                Some((
+                    key.to_string_lossy().to_string(),
+                    value?.to_string_lossy().to_string(),
+                ))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        // `build` passes these as command line flags, so that plain `cargo build`
+        // with this environment builds for Vita as well.
+        vars.insert("CARGO_BUILD_TARGET".to_string(), VITA_TARGET.to_string());
+        vars.insert("CARGO_UNSTABLE_BUILD_STD".to_string(), meta.build_std);
+
+        if let EnvFormat::Json = self.format {
+            let json =
+                serde_json::to_string_pretty(&vars).context("Unable to serialize environment")?;
+            println!("{json}");
+            return Ok(());
+        }
+
+        for (key, value) in &vars {
+            match self.format {
+                EnvFormat::Bash => println!("export {key}='{}'", value.replace('\'', r"'\''")),
+                EnvFormat::Fish => println!(
+                    "set -gx {key} '{}'",
+                    value.replace('\\', r"\\").replace('\'', r"\'")
+                ),
+                EnvFormat::Powershell => println!("$env:{key} = '{}'", value.replace('\'', "''")),
+                EnvFormat::Json => {}
+            }
+        }
+
+        Ok(())
+    }
+}
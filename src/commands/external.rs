@@ -0,0 +1,48 @@
+use std::process::Stdio;
+
+use anyhow::{bail, Context};
+use colored::Colorize;
+use log::info;
+
+use crate::{check, meta::parse_crate_metadata, sdk::VitaSdk};
+
+use super::Executor;
+
+/// Cargo subcommands that build for a target, and accept `--target` and `-Z build-std`.
+/// Others, like `fmt`, reject them, and only get the environment.
+static TARGET_SUBCOMMANDS: &[&str] = &[
+    "bench", "build", "b", "check", "c", "clean", "clippy", "doc", "d", "fetch", "fix", "miri",
+    "run", "r", "rustc", "rustdoc", "test", "t", "tree",
+];
+
+/// Commands not recognized by `cargo-vita`, collected by clap as a raw list of arguments,
+/// where the first one is the cargo subcommand.
+#[derive(Debug)]
+pub struct External(pub Vec<String>);
+
+impl Executor for External {
+    fn execute(&self) -> anyhow::Result<()> {
+        let (subcommand, args) = self.0.split_first().context("No cargo command provided")?;
+        let (meta, _, _) = parse_crate_metadata()?;
+        let sdk = VitaSdk::from_env()?;
+        let mut command = if TARGET_SUBCOMMANDS.contains(&subcommand.as_str()) {
+            check::rust_toolchain(meta.min_nightly.as_deref())?;
+            sdk.cargo(subcommand, &meta.build_std)
+        } else {
+            sdk.cargo_env(subcommand)
+        };
+        command
+            .args(args)
+            .stdin(Stdio::inherit())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+
+        info!("{}: {command:?}", "Running cargo".blue());
+
+        if !command.status()?.success() {
+            bail!("cargo {subcommand} failed");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use cargo_metadata::camino::Utf8Path;
+use clap::Args;
+use colored::Colorize;
+use log::info;
+use serde_json::{json, Map, Value};
+use toml_edit::{value, Array, DocumentMut, Table};
+
+use crate::{
+    meta::{WorkspaceMetadata, VITA_TARGET},
+    sdk::VitaSdk,
+};
+
+use super::Executor;
+
+#[derive(Args, Debug)]
+pub struct Ide {
+    /// Do not write `.cargo/config.toml`, only the VS Code configuration.
+    #[arg(long)]
+    no_cargo_config: bool,
+}
+
+impl Executor for Ide {
+    fn execute(&self) -> anyhow::Result<()> {
+        let workspace = WorkspaceMetadata::load()?;
+        let (meta, _) = workspace.package_metadata()?;
+        let workspace_root = workspace.workspace_root();
+
+        // The SDK paths are written relative to `${env:VITASDK}`, so that the settings can be committed.
+        // Both check commands run through `cargo vita`, which sets up the same environment on its own.
+        let sdk = VitaSdk::portable();
+        let mut extra_env = sdk
+            .target_env()
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Value::String(value)))
+            .collect::<Map<_, _>>();
+        extra_env.insert("RUSTFLAGS".into(), json!(sdk.rustflags().join(" ")));
+
+        let vscode = workspace_root.join(".vscode");
+
+        update_json(&vscode.join("settings.json"), |settings| {
+            settings.insert("rust-analyzer.cargo.target".into(), json!(VITA_TARGET));
+            settings.insert("rust-analyzer.cargo.extraEnv".into(), extra_env.into());
+            settings.insert(
+                "rust-analyzer.cargo.buildScripts.overrideCommand".into(),
+                json!(["cargo", "vita", "check", "--quiet", "--message-format=json"]),
+            );
+            settings.insert(
+                "rust-analyzer.check.overrideCommand".into(),
+                json!(["cargo", "vita", "check", "--message-format=json"]),
+            );
+
+            Ok(())
+        })?;
+
+        update_json(&vscode.join("tasks.json"), |tasks| {
+            tasks.entry("version").or_insert(json!("2.0.0"));
+            let tasks = array_entry(tasks, "tasks")?;
+
+            for (label, args, group) in [
+                (
+                    "cargo vita: build vpk",
+                    ["vita", "build", "vpk"].as_slice(),
+                    "build",
+                ),
+                (
+                    "cargo vita: upload vpk",
+                    &["vita", "build", "vpk", "--upload"],
+                    "none",
+                ),
+                (
+                    "cargo vita: run",
+                    &["vita", "build", "eboot", "--update", "--run"],
+                    "test",
+                ),
+            ] {
+                merge_by_key(
+                    tasks,
+                    "label",
+                    json!({
+                        "label": label,
+                        "type": "shell",
+                        "command": "cargo",
+                        "args": args,
+                        "problemMatcher": ["$rustc"],
+                        "group": group,
+                    }),
+                );
+            }
+
+            Ok(())
+        })?;
+
+        update_json(&vscode.join("launch.json"), |launch| {
+            launch.entry("version").or_insert(json!("0.2.0"));
+            merge_by_key(
+                array_entry(launch, "configurations")?,
+                "name",
+                json!({
+                    "name": "cargo vita: logs",
+                    "type": "node-terminal",
+                    "request": "launch",
+                    "command": "cargo vita logs",
+                }),
+            );
+
+            Ok(())
+        })?;
+
+        if !self.no_cargo_config {
+            update_cargo_config(&workspace_root.join(".cargo").join("config.toml"), |doc| {
+                table(doc, "build")?.insert("target", value(VITA_TARGET));
+                table(doc, "unstable")?.insert(
+                    "build-std",
+                    value(meta.build_std.split(',').map(str::trim).collect::<Array>()),
+                );
+
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a JSON object from `path` (or starts with an empty one), updates it and writes it back.
+fn update_json(
+    path: &Utf8Path,
+    update: impl FnOnce(&mut Map<String, Value>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut object = match read_if_exists(path.as_std_path())? {
+        Some(content) => serde_json::from_str::<Map<_, _>>(&content).with_context(|| {
+            format!(
+                "Unable to parse {path}. Comments and trailing commas are not supported, \
+                please remove them or merge the configuration manually"
+            )
+        })?,
+        None => Map::new(),
+    };
+
+    update(&mut object).with_context(|| format!("Unable to update {path}"))?;
+
+    let json = serde_json::to_string_pretty(&object).context("Unable to serialize json")?;
+    write(path, &(json + "\n"))
+}
+
+fn update_cargo_config(
+    path: &Utf8Path,
+    update: impl FnOnce(&mut DocumentMut) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut doc = read_if_exists(path.as_std_path())?
+        .unwrap_or_default()
+        .parse::<DocumentMut>()
+        .with_context(|| format!("Unable to parse {path}"))?;
+
+    update(&mut doc).with_context(|| format!("Unable to update {path}"))?;
+
+    write(path, &doc.to_string())
+}
+
+fn read_if_exists(path: &Path) -> anyhow::Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    fs::read_to_string(path)
+        .map(Some)
+        .with_context(|| format!("Unable to read {}", path.display()))
+}
+
+fn write(path: &Utf8Path, content: &str) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Unable to create {parent}"))?;
+    }
+
+    info!("{} {path}", "Writing".blue());
+
+    fs::write(path, content).with_context(|| format!("Unable to write {path}"))
+}
+
+fn array_entry<'a>(
+    object: &'a mut Map<String, Value>,
+    key: &str,
+) -> anyhow::Result<&'a mut Vec<Value>> {
+    object
+        .entry(key)
+        .or_insert_with(|| json!([]))
+        .as_array_mut()
+        .with_context(|| format!("`{key}` is expected to be an array"))
+}
+
+/// Replaces the item with the same `key` field as `item`, or appends it.
+fn merge_by_key(array: &mut Vec<Value>, key: &str, item: Value) {
+    match array.iter_mut().find(|existing| existing[key] == item[key]) {
+        Some(existing) => *existing = item,
+        None => array.push(item),
+    }
+}
+
+fn table<'a>(doc: &'a mut DocumentMut, key: &str) -> anyhow::Result<&'a mut Table> {
+    doc.entry(key)
+        .or_insert(toml_edit::table())
+        .as_table_mut()
+        .with_context(|| format!("`{key}` is expected to be a table"))
+}
@@ -1,21 +1,33 @@
 use anyhow::Context;
+use cargo_metadata::camino::Utf8PathBuf;
 use clap::{Args, Parser, Subcommand};
-use enum_dispatch::enum_dispatch;
 
 pub use build::*;
+pub use check_clang::*;
+pub use check_imports::*;
 pub use coredump::*;
+pub use env::*;
+pub use external::*;
+pub use ide::*;
 pub use logs::*;
 pub use reboot::*;
 pub use run::*;
+pub use schema::*;
 pub use upload::*;
 
-use crate::meta::TitleId;
+use crate::{meta::TitleId, symbols::SymbolStore};
 
 mod build;
+mod check_clang;
+mod check_imports;
 mod coredump;
+mod env;
+mod external;
+mod ide;
 mod logs;
 mod reboot;
 mod run;
+mod schema;
 mod upload;
 
 #[derive(Parser, Debug)]
@@ -39,14 +51,17 @@ pub struct Input {
     /// Passing this flag multiple times will enable verbose mode for the rust compiler.
     #[arg(long, short = 'v', action = clap::ArgAction::Count)]
     pub verbose: u8,
+
+    /// Fail on unknown keys in `package.metadata.vita`, instead of printing a warning.
+    #[arg(long, global = true, env = "CARGO_VITA_STRICT")]
+    pub strict: bool,
 }
 
 /// Run a cargo command. COMMAND will be forwarded to the real
-/// `cargo` with the appropriate arguments for the 3DS target.
+/// `cargo` with the appropriate arguments for the Vita target.
 ///
 /// If an unrecognized COMMAND is used, it will be passed through unmodified
-/// to `cargo` with the appropriate flags set for the 3DS target.
-#[enum_dispatch]
+/// to `cargo` with the appropriate flags set for the Vita target.
 #[derive(Subcommand, Debug)]
 #[command(allow_external_subcommands = true)]
 pub enum CargoCmd {
@@ -56,19 +71,53 @@ pub enum CargoCmd {
     Upload(Upload),
     /// Starts an installed title on the Vita by the title id.
     Run(Run),
-    /// Start a TCP server on this machine, to which Vita can stream logs via PrincessLog.
+    /// Start a TCP server on this machine, to which Vita can stream logs via `PrincessLog`.
     Logs(Logs),
     /// Download coredump files from the Vita.
     Coredump(Coredump),
     /// Reboot the Vita
     Reboot(Reboot),
+    /// Print the environment `cargo-vita` builds with, as shell exports or JSON.
+    Env(Env),
+    /// Write rust-analyzer and VS Code configuration for the Vita target into the workspace.
+    Ide(Ide),
+    /// Check that clang can parse the SDK headers with the arguments `cargo-vita` passes to bindgen.
+    CheckClang(CheckClang),
+    /// Check that the imports of the built elf files resolve on the given firmware versions.
+    CheckImports(CheckImports),
+    /// Print the JSON Schema of `package.metadata.vita`, for editors with TOML schema support.
+    Schema(Schema),
+    /// Any other cargo command (e.g. `check`, `clippy`, `doc`, `tree`),
+    /// run with the same environment and flags as `build`. Commands that do not build
+    /// for a target (e.g. `fmt`) are run with the environment only.
+    #[command(external_subcommand)]
+    External(Vec<String>),
 }
 
-#[enum_dispatch(CargoCmd)]
 pub trait Executor {
     fn execute(&self) -> anyhow::Result<()>;
 }
 
+impl Executor for CargoCmd {
+    fn execute(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Build(cmd) => cmd.execute(),
+            Self::Upload(cmd) => cmd.execute(),
+            Self::Run(cmd) => cmd.execute(),
+            Self::Logs(cmd) => cmd.execute(),
+            Self::Coredump(cmd) => cmd.execute(),
+            Self::Reboot(cmd) => cmd.execute(),
+            Self::Env(cmd) => cmd.execute(),
+            Self::Ide(cmd) => cmd.execute(),
+            Self::CheckClang(cmd) => cmd.execute(),
+            Self::CheckImports(cmd) => cmd.execute(),
+            Self::Schema(cmd) => cmd.execute(),
+            // clap only collects external subcommands into a `Vec`
+            Self::External(args) => External(args.clone()).execute(),
+        }
+    }
+}
+
 #[derive(Args, Debug)]
 pub struct TitleArgs {
     #[arg(long, short = 't')]
@@ -117,3 +166,30 @@ pub struct TitleIdArg {
     #[arg(long, short = 'i', value_parser = clap::value_parser!(TitleId))]
     title_id: Option<TitleId>,
 }
+
+#[derive(Args, Debug, Clone)]
+pub struct SymbolStoreArgs {
+    /// A directory where unstripped elf files are archived by their GNU build-id,
+    /// along with the modules created from them.
+    /// Defaults to `vita-symbols` in the cargo home, so that it survives `cargo clean`.
+    #[arg(long, env = "VITA_SYMBOL_STORE")]
+    pub symbol_store: Option<Utf8PathBuf>,
+    /// The number of most recently archived elf files kept in the symbol store for every title.
+    #[arg(long, env = "VITA_SYMBOL_STORE_KEEP", default_value_t = 20)]
+    pub symbol_store_keep: usize,
+}
+
+impl SymbolStoreArgs {
+    pub fn store(&self) -> anyhow::Result<SymbolStore> {
+        let root = if let Some(root) = &self.symbol_store {
+            root.clone()
+        } else {
+            let cargo_home = home::cargo_home().context("Unable to find the cargo home")?;
+            Utf8PathBuf::from_path_buf(cargo_home)
+                .map_err(|path| anyhow::anyhow!("Cargo home {} is not UTF-8", path.display()))?
+                .join("vita-symbols")
+        };
+
+        Ok(SymbolStore::new(root, self.symbol_store_keep))
+    }
+}
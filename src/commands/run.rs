@@ -18,7 +18,7 @@ impl Executor for Run {
     fn execute(&self) -> anyhow::Result<()> {
         let title_id = match &self.title_id {
             Some(title_id) => title_id.clone(),
-            None => parse_crate_metadata(None)?.0.title_id
+            None => parse_crate_metadata()?.0.title_id
             .context("Title id must either be provided by a flag or set in the `package.metadata.vita.title_id` field of your Cargo.toml")?,
         };
 
@@ -0,0 +1,59 @@
+use anyhow::Context;
+use clap::Args;
+use schemars::{
+    gen::SchemaSettings,
+    schema::{self, ObjectValidation, RootSchema, SchemaObject},
+};
+
+use crate::meta::PackageMetadata;
+
+use super::Executor;
+
+#[derive(Args, Debug)]
+pub struct Schema {
+    /// Nest the schema under `package.metadata.vita`, so that it can be associated with `Cargo.toml` files.
+    #[arg(long)]
+    manifest: bool,
+}
+
+impl Executor for Schema {
+    fn execute(&self) -> anyhow::Result<()> {
+        // TOML has no null, optional keys are simply omitted
+        let mut schema = SchemaSettings::draft07()
+            .with(|settings| settings.option_add_null_type = false)
+            .into_generator()
+            .into_root_schema_for::<PackageMetadata>();
+
+        if self.manifest {
+            schema = nest_in_manifest(schema);
+        }
+
+        let json = serde_json::to_string_pretty(&schema).context("Unable to serialize schema")?;
+        println!("{json}");
+
+        Ok(())
+    }
+}
+
+/// Moves the metadata schema to `package.metadata.vita`, leaving the rest of the manifest unconstrained.
+fn nest_in_manifest(mut schema: RootSchema) -> RootSchema {
+    let vita = std::mem::take(&mut schema.schema);
+    let manifest = object("package", object("metadata", object("vita", vita)));
+
+    RootSchema {
+        schema: manifest,
+        ..schema
+    }
+}
+
+fn object(key: &str, schema: SchemaObject) -> SchemaObject {
+    SchemaObject {
+        object: Some(Box::new(ObjectValidation {
+            properties: [(key.to_string(), schema::Schema::Object(schema))]
+                .into_iter()
+                .collect(),
+            ..ObjectValidation::default()
+        })),
+        ..SchemaObject::default()
+    }
+}
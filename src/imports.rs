@@ -0,0 +1,318 @@
+use std::{collections::HashMap, fmt::Display, fs};
+
+use anyhow::Context;
+use cargo_metadata::camino::Utf8Path;
+use object::{Object, ObjectSection, ObjectSymbol};
+
+use crate::nids::{Library, NidDb};
+
+/// The linker collects import stubs into these sections, one per imported library
+/// (e.g. `.vitalink.fstubs.SceLibKernel`), and `vita-elf-create` builds the import table of the velf from them.
+static FUNCTION_STUBS_SECTION: &str = ".vitalink.fstubs";
+static VARIABLE_STUBS_SECTION: &str = ".vitalink.vstubs";
+
+/// Every stub is four words: flags, library NID, function or variable NID, and a reserved word.
+const STUB_SIZE: usize = 16;
+
+/// Libraries that only unsafe homebrew can import. Safe apps importing them fail to start.
+static UNSAFE_ONLY_LIBRARIES: &[(&str, &str)] = &[
+    (
+        "SceVshBridge",
+        "VSH bridge is only available to unsafe apps.",
+    ),
+    (
+        "SceRegMgr",
+        "Direct access to the system registry is only available to unsafe apps.",
+    ),
+];
+
+/// Suffixes of libraries exported for system software only.
+static UNSAFE_ONLY_LIBRARY_SUFFIXES: &[(&str, &str)] = &[
+    (
+        "ForVsh",
+        "Libraries for system applications are only available to unsafe apps.",
+    ),
+    (
+        "ForShell",
+        "Libraries for SceShell are only available to unsafe apps.",
+    ),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportKind {
+    Function,
+    Variable,
+}
+
+impl Display for ImportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Function => write!(f, "function"),
+            Self::Variable => write!(f, "variable"),
+        }
+    }
+}
+
+/// A function or a variable imported from another module.
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub kind: ImportKind,
+    /// The library name, taken from the stub section name.
+    pub library: Option<String>,
+    pub library_nid: u32,
+    pub nid: u32,
+    /// The name of the stub symbol, if the elf still has it.
+    pub name: Option<String>,
+}
+
+impl Import {
+    /// Looks up the library of this import in the NID databases.
+    pub fn find_library<'a>(&self, dbs: &'a [NidDb]) -> Option<(&'a str, &'a Library)> {
+        dbs.iter().find_map(|db| db.find_library(self.library_nid))
+    }
+
+    /// Fills in the library and symbol names from the NID databases, where the elf does not have them.
+    pub fn resolve_names(&mut self, dbs: &[NidDb]) {
+        if let Some((library_name, library)) = self.find_library(dbs) {
+            if self.library.is_none() {
+                self.library = Some(library_name.to_string());
+            }
+
+            if self.name.is_none() {
+                self.name = library.symbol_name(self.nid).map(ToString::to_string);
+            }
+        }
+    }
+}
+
+impl Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.library {
+            Some(library) => write!(f, "{library}")?,
+            None => write!(f, "0x{:08X}", self.library_nid)?,
+        }
+
+        match &self.name {
+            Some(name) => write!(f, "::{name}"),
+            None => write!(f, "::0x{:08X}", self.nid),
+        }
+    }
+}
+
+/// Reads the imported functions and variables from the import stubs of the elf.
+pub fn read_imports(elf: &Utf8Path) -> anyhow::Result<Vec<Import>> {
+    let data = fs::read(elf).context("Unable to read elf file")?;
+    let file = object::File::parse(&*data).context("Unable to parse elf file")?;
+
+    let symbols = file
+        .symbols()
+        .filter(ObjectSymbol::is_definition)
+        .filter_map(|s| Some((s.address(), s.name().ok()?)))
+        .collect::<HashMap<_, _>>();
+
+    let mut imports = Vec::new();
+
+    for section in file.sections() {
+        let section_name = section.name().context("Unable to read section name")?;
+
+        let (kind, library) = if let Some(rest) = section_name.strip_prefix(FUNCTION_STUBS_SECTION)
+        {
+            (ImportKind::Function, rest)
+        } else if let Some(rest) = section_name.strip_prefix(VARIABLE_STUBS_SECTION) {
+            (ImportKind::Variable, rest)
+        } else {
+            continue;
+        };
+
+        let library = library
+            .strip_prefix('.')
+            .filter(|l| !l.is_empty())
+            .map(ToString::to_string);
+
+        let stubs = section
+            .data()
+            .with_context(|| format!("Unable to read {section_name} section"))?;
+
+        for (address, stub) in (section.address()..)
+            .step_by(STUB_SIZE)
+            .zip(stubs.chunks_exact(STUB_SIZE))
+        {
+            let word =
+                |i: usize| u32::from_le_bytes([stub[i], stub[i + 1], stub[i + 2], stub[i + 3]]);
+
+            // Thumb function symbols have the lowest bit set
+            let name = symbols
+                .get(&address)
+                .or_else(|| symbols.get(&(address | 1)))
+                .map(ToString::to_string);
+
+            imports.push(Import {
+                kind,
+                library: library.clone(),
+                library_nid: word(4),
+                nid: word(8),
+                name,
+            });
+        }
+    }
+
+    Ok(imports)
+}
+
+/// Returns the reason why the import is only available to unsafe apps, if it is.
+pub fn unsafe_only_reason(import: &Import, dbs: &[NidDb]) -> Option<&'static str> {
+    if import
+        .find_library(dbs)
+        .is_some_and(|(_, library)| library.kernel)
+    {
+        return Some("Kernel libraries can't be imported by apps.");
+    }
+
+    let library = import.library.as_deref()?;
+
+    UNSAFE_ONLY_LIBRARIES
+        .iter()
+        .find(|(name, _)| *name == library)
+        .or_else(|| {
+            UNSAFE_ONLY_LIBRARY_SUFFIXES
+                .iter()
+                .find(|(suffix, _)| library.ends_with(suffix))
+        })
+        .map(|(_, reason)| *reason)
+}
+
+/// Returns the databases for the given firmware version, e.g. `3.60`.
+pub fn firmware_dbs<'a>(dbs: &'a [NidDb], firmware: &str) -> Vec<&'a NidDb> {
+    let firmware = normalize_firmware(firmware);
+
+    dbs.iter()
+        .filter(|db| {
+            db.firmware
+                .as_deref()
+                .is_some_and(|f| normalize_firmware(f) == firmware)
+        })
+        .collect()
+}
+
+/// Firmware versions are compared as numbers, so that `3.6` and `3.60` are the same version.
+fn normalize_firmware(firmware: &str) -> String {
+    match firmware.trim().parse::<f64>() {
+        Ok(n) => format!("{n:.2}"),
+        Err(_) => firmware.trim().to_string(),
+    }
+}
+
+/// Returns the imports that would not resolve with the given firmware databases, with the reason.
+/// Libraries that none of `dbs` know about (e.g. of third-party plugins) are not part of
+/// any firmware, and are skipped.
+pub fn unresolved_imports<'a>(
+    imports: &'a [Import],
+    dbs: &[NidDb],
+    firmware_dbs: &[&NidDb],
+) -> Vec<(&'a Import, &'static str)> {
+    imports
+        .iter()
+        .filter(|import| {
+            dbs.iter()
+                .any(|db| db.find_library(import.library_nid).is_some())
+        })
+        .filter_map(|import| {
+            let Some((_, library)) = firmware_dbs
+                .iter()
+                .find_map(|db| db.find_library(import.library_nid))
+            else {
+                return Some((import, "library NID is not exported by this firmware"));
+            };
+
+            let symbols = match import.kind {
+                ImportKind::Function => &library.functions,
+                ImportKind::Variable => &library.variables,
+            };
+
+            (!symbols.values().any(|nid| *nid == import.nid))
+                .then_some((import, "NID is not exported by this firmware"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db(firmware: &str, yaml: &str) -> NidDb {
+        NidDb {
+            firmware: Some(firmware.to_string()),
+            ..serde_yaml::from_str(yaml).unwrap()
+        }
+    }
+
+    fn import(library_nid: u32, nid: u32) -> Import {
+        Import {
+            kind: ImportKind::Function,
+            library: None,
+            library_nid,
+            nid,
+            name: None,
+        }
+    }
+
+    const KERNEL_360: &str = "
+modules:
+  SceLibKernel:
+    libraries:
+      SceLibKernel:
+        nid: 0xCAE9ACE6
+        functions:
+          sceKernelGetProcessId: 0x9DCB4B7A
+";
+
+    const KERNEL_365: &str = "
+modules:
+  SceLibKernel:
+    libraries:
+      SceLibKernel:
+        nid: 0xCAE9ACE6
+        functions:
+          sceKernelGetProcessId: 0x9DCB4B7A
+          sceKernelGetThreadId: 0x0FB972F9
+      SceNewLibrary:
+        nid: 0x12345678
+";
+
+    #[test]
+    fn missing_symbols_are_unresolved() {
+        let dbs = [db("3.60", KERNEL_360), db("3.65", KERNEL_365)];
+        let imports = [
+            import(0xCAE9_ACE6, 0x9DCB_4B7A),
+            import(0xCAE9_ACE6, 0x0FB9_72F9),
+        ];
+
+        let unresolved = unresolved_imports(&imports, &dbs, &[&dbs[0]]);
+        assert_eq!(unresolved.len(), 1);
+        assert_eq!(unresolved[0].0.nid, 0x0FB9_72F9);
+        assert_eq!(unresolved[0].1, "NID is not exported by this firmware");
+
+        assert!(unresolved_imports(&imports, &dbs, &[&dbs[1]]).is_empty());
+    }
+
+    #[test]
+    fn libraries_missing_from_firmware_are_unresolved() {
+        let dbs = [db("3.60", KERNEL_360), db("3.65", KERNEL_365)];
+        let imports = [import(0x1234_5678, 0)];
+
+        let unresolved = unresolved_imports(&imports, &dbs, &[&dbs[0]]);
+        assert_eq!(unresolved.len(), 1);
+        assert_eq!(
+            unresolved[0].1,
+            "library NID is not exported by this firmware"
+        );
+    }
+
+    #[test]
+    fn unknown_libraries_are_skipped() {
+        let dbs = [db("3.60", KERNEL_360)];
+        let imports = [import(0xDEAD_BEEF, 0x0BAD_F00D)];
+
+        assert!(unresolved_imports(&imports, &dbs, &[&dbs[0]]).is_empty());
+    }
+}
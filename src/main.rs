@@ -1,16 +1,22 @@
 mod check;
 mod commands;
 mod ftp;
+mod imports;
 mod meta;
+mod module_info;
 mod nc;
+mod nids;
+mod sdk;
+mod symbols;
 
 use clap::Parser;
 use colored::Colorize;
 use commands::{Cargo, Executor};
-use log::error;
+use log::{error, warn};
 
 fn main() {
-    let _ = check::set_cargo_config_env();
+    // Must be applied before parsing the arguments, since some of them can be set via env
+    let config_env = check::set_cargo_config_env();
 
     let Cargo::Input(input) = Cargo::parse();
 
@@ -25,7 +31,16 @@ fn main() {
         })
         .init();
 
+    if let Err(err) = config_env {
+        warn!(
+            "{} {err:#}",
+            "Unable to apply `[env]` section of cargo config:".yellow()
+        );
+    }
+
     let Cargo::Input(input) = Cargo::parse();
+    meta::set_strict(input.strict);
+
     match input.cmd.execute() {
         Ok(()) => {}
         Err(e) => {
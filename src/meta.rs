@@ -1,8 +1,29 @@
-use std::{collections::HashMap, fmt::Display, ops::Deref, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    ops::Deref,
+    str::FromStr,
+};
 
-use anyhow::Context;
-use cargo_metadata::{camino::Utf8PathBuf, Artifact, MetadataCommand, Package};
-use serde::Deserialize;
+use anyhow::{anyhow, bail, Context};
+use cargo_metadata::{
+    camino::{Utf8Path, Utf8PathBuf},
+    Artifact, Metadata, MetadataCommand, Package,
+};
+use colored::Colorize;
+use log::warn;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, StringValidation, SubschemaValidation},
+    JsonSchema,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::nids::Module;
+
+pub use self::unknown_keys::set_strict;
+
+mod unknown_keys;
 
 pub static VITA_TARGET: &str = "armv7-sony-vita-newlibeabihf";
 
@@ -19,6 +40,40 @@ impl<'de> Deserialize<'de> for TitleId {
     }
 }
 
+impl JsonSchema for TitleId {
+    fn schema_name() -> String {
+        "TitleId".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_schema(
+            "A unique identifier of the title, 9 alphanumeric characters starting with a letter.",
+            "^[A-Za-z][A-Za-z0-9]{8}$",
+        )
+    }
+}
+
+impl TitleId {
+    /// Derives a title id from a 4-letter prefix and a hash of the seed, e.g. `RUST04217`.
+    pub fn derive(prefix: &TitleIdPrefix, seed: &str) -> Self {
+        // FNV-1a, so that the id is stable across builds and Rust versions
+        let hash = seed.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+        Self(format!("{}{:05}", prefix.0, hash % 100_000))
+    }
+
+    /// The derived title id following this one, with the same prefix.
+    /// Used to probe for a free id when derived title ids collide.
+    pub fn next_derived(&self) -> Self {
+        let (prefix, number) = self.0.split_at(4);
+        let number = number.parse::<u32>().map_or(0, |n| (n + 1) % 100_000);
+
+        Self(format!("{prefix}{number:05}"))
+    }
+}
+
 impl Deref for TitleId {
     type Target = str;
 
@@ -54,39 +109,329 @@ impl FromStr for TitleId {
             return Err("Title ID must start with an alphabetic character".to_string());
         }
 
-        Ok(Self(s.to_uppercase().to_string()))
+        Ok(Self(s.to_uppercase()))
     }
 }
 
-fn default_build_std() -> String {
-    "std,panic_unwind".to_string()
+/// The first four letters of the title ids derived for tests and examples.
+#[derive(Clone, Debug)]
+pub struct TitleIdPrefix(String);
+
+impl<'de> Deserialize<'de> for TitleIdPrefix {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for TitleIdPrefix {
+    fn schema_name() -> String {
+        "TitleIdPrefix".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        string_schema(
+            "The first 4 letters of the title ids derived for tests and examples.",
+            "^[A-Za-z]{4}$",
+        )
+    }
 }
 
-fn default_vita_make_fself_flags() -> Vec<String> {
-    vec!["-s".to_string()]
+impl FromStr for TitleIdPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 4 || !s.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err("Title id prefix must be 4 letters".to_string());
+        }
+
+        Ok(Self(s.to_uppercase()))
+    }
+}
+
+/// A size in bytes. Can be deserialized either from an integer number of bytes,
+/// or from a string with a unit suffix, e.g. `"512 KiB"` or `"2MB"`.
+#[derive(Clone, Copy, Debug)]
+pub struct ByteSize(pub u64);
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            String(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(Self(bytes)),
+            Raw::String(s) => FromStr::from_str(&s).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl JsonSchema for ByteSize {
+    fn schema_name() -> String {
+        "ByteSize".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![
+                    gen.subschema_for::<u64>(),
+                    string_schema(
+                        "A size with a unit, e.g. `512 KiB` or `2MB`.",
+                        r"^\s*[0-9.]+\s*([bB]|[kKmMgG]([iI]?[bB])?)?\s*$",
+                    ),
+                ]),
+                ..SubschemaValidation::default()
+            })),
+            ..SchemaObject::default()
+        }
+        .into()
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kib" => 1 << 10,
+            "m" | "mib" => 1 << 20,
+            "g" | "gib" => 1 << 30,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            unit => return Err(format!("Unknown size unit `{unit}`")),
+        };
+
+        let number = number
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid size `{s}`"))?;
+
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss
+        )]
+        Ok(Self((number * multiplier as f64) as u64))
+    }
+}
+
+/// A string schema with a description and a regex the string must match.
+fn string_schema(description: &str, pattern: &str) -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: Some(pattern.to_string()),
+            ..StringValidation::default()
+        })),
+        ..SchemaObject::default()
+    };
+    schema.metadata().description = Some(description.to_string());
+    schema.into()
+}
+
+fn default_build_std() -> String {
+    "std,panic_unwind".to_string()
 }
 
 fn default_vita_mksfoex_flags() -> Vec<String> {
     vec!["-d".to_string(), "ATTRIBUTE2=12".to_string()]
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, JsonSchema, Debug)]
 pub struct PackageMetadata {
+    /// A unique identifier of the title.
     pub title_id: Option<TitleId>,
+    /// The prefix of title ids derived for tests and examples.
+    /// Defaults to the first 4 characters of `title_id`, if they are letters.
+    pub title_id_prefix: Option<TitleIdPrefix>,
+    /// The seed `title_id` was derived from, if it was derived for a test or an example.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub title_id_seed: Option<String>,
+    /// A title shown on the bubble. Defaults to the crate name.
     pub title_name: Option<String>,
+    /// A path to static files packed into the vpk, relative to the crate.
     pub assets: Option<String>,
+    /// Crates passed to `-Zbuild-std`.
     #[serde(default = "default_build_std")]
     pub build_std: String,
-    #[serde(default = "default_vita_make_fself_flags")]
-    pub vita_make_fself_flags: Vec<String>,
+    /// The oldest nightly the package can be built with, as a `YYYY-MM-DD` date.
+    #[schemars(regex(pattern = r"^\d{4}-\d{2}-\d{2}$"))]
+    pub min_nightly: Option<String>,
+    /// Raw flags for `vita-make-fself`. Can't be used together with `fself`.
+    pub vita_make_fself_flags: Option<Vec<String>>,
+    /// Options for `vita-make-fself`. The defaults create a safe app.
+    pub fself: Option<FselfOptions>,
+    /// Flags for `vita-mksfoex`.
     #[serde(default = "default_vita_mksfoex_flags")]
     pub vita_mksfoex_flags: Vec<String>,
 
+    /// Settings for cargo profiles, by the profile name.
     #[serde(default)]
     pub profile: HashMap<String, ProfileMetadata>,
+
+    #[serde(default)]
+    pub size_limits: SizeLimits,
+
+    #[serde(default)]
+    pub plugin: PluginMetadata,
+
+    #[serde(default)]
+    pub imports: ImportsMetadata,
+
+    /// Firmware versions the imports are checked against after the velf is created.
+    #[serde(default)]
+    pub firmware: Vec<String>,
+
+    /// Overrides for binaries, by the target name.
+    #[serde(default)]
+    pub bin: HashMap<String, TargetMetadata>,
+    /// Overrides for examples, by the target name.
+    #[serde(default)]
+    pub example: HashMap<String, TargetMetadata>,
+    /// Overrides for integration tests, by the target name.
+    #[serde(default)]
+    pub test: HashMap<String, TargetMetadata>,
+    /// Overrides for artifacts built with a cargo feature, by the feature name.
+    /// Applied before the overrides of the target.
+    #[serde(default)]
+    pub features: BTreeMap<String, TargetMetadata>,
 }
 
 impl PackageMetadata {
+    /// The options to create the eboot with, either from `fself` section or parsed from `vita_make_fself_flags`.
+    pub fn fself_options(&self) -> anyhow::Result<FselfOptions> {
+        match (&self.vita_make_fself_flags, &self.fself) {
+            (Some(_), Some(_)) => bail!(
+                "`vita_make_fself_flags` and `fself` can't be used together in `[package.metadata.vita]`"
+            ),
+            (Some(flags), None) => {
+                let options = FselfOptions::from_flags(flags)
+                    .map_err(|err| anyhow!(err))
+                    .context("Invalid `vita_make_fself_flags`")?;
+
+                if !options.extra_flags.is_empty() {
+                    warn!(
+                        "{} {}",
+                        "Passing unknown `vita_make_fself_flags` to vita-make-fself as is:".yellow(),
+                        options.extra_flags.join(" ")
+                    );
+                }
+
+                Ok(options)
+            }
+            (None, fself) => Ok(fself.clone().unwrap_or_default()),
+        }
+    }
+
+    /// Applies the overrides of the features activated for the artifact, in alphabetical order,
+    /// and then of the cargo target the artifact was built from.
+    /// Tests and examples without overrides get their own title id and name,
+    /// so that they can be installed side by side.
+    pub fn apply_overrides(&mut self, package: &str, artifact: &Artifact) {
+        let features = self
+            .features
+            .iter()
+            .filter(|(feature, _)| artifact.features.contains(feature))
+            .map(|(feature, overrides)| (feature.clone(), overrides.clone()))
+            .collect::<Vec<_>>();
+
+        for (_, overrides) in &features {
+            self.merge(overrides.clone());
+        }
+
+        let target = &artifact.target;
+        let overrides = target
+            .kind
+            .iter()
+            .find_map(|kind| {
+                match kind.as_str() {
+                    // Unit tests of a binary have the kind of the binary, but must not share its id
+                    "bin" | "example" if artifact.profile.test => return None,
+                    "bin" => &self.bin,
+                    "example" => &self.example,
+                    "test" => &self.test,
+                    _ => return None,
+                }
+                .get(&target.name)
+            })
+            .cloned()
+            .unwrap_or_default();
+
+        let derived = overrides.title_id.is_none().then(|| {
+            let kind = artifact_kind(artifact)?;
+            let prefix = self.title_id_prefix.clone().or_else(|| {
+                self.title_id
+                    .as_ref()
+                    .and_then(|id| id.get(..4)?.parse().ok())
+            })?;
+
+            // Variants built with different features must not overwrite each other
+            let seed = [package, kind, &target.name]
+                .into_iter()
+                .chain(features.iter().map(|(feature, _)| feature.as_str()))
+                .collect::<Vec<_>>()
+                .join("/");
+
+            Some((TitleId::derive(&prefix, &seed), seed))
+        });
+        let (title_id, seed) = derived.flatten().unzip();
+        let title_id = overrides.title_id.or(title_id);
+        self.title_id_seed = seed;
+
+        let title_name = overrides.title_name.or_else(|| {
+            let kind = artifact_kind(artifact)?;
+            let name = self.title_name.as_deref().unwrap_or(package);
+
+            Some(format!("{name} {kind} {}", target.name))
+        });
+
+        self.merge(TargetMetadata {
+            title_id,
+            title_name,
+            assets: overrides.assets,
+        });
+    }
+
+    fn merge(&mut self, overrides: TargetMetadata) {
+        self.title_id = overrides.title_id.or(self.title_id.take());
+        self.title_name = overrides.title_name.or(self.title_name.take());
+        self.assets = overrides.assets.or(self.assets.take());
+    }
+
     pub fn strip_symbols(&self, profile: &str) -> bool {
         let default = profile == "release";
 
@@ -97,51 +442,673 @@ impl PackageMetadata {
     }
 }
 
-#[derive(Deserialize, Debug, Default)]
+/// Overrides of the package metadata for a single cargo target or feature.
+#[derive(Deserialize, JsonSchema, Debug, Default, Clone)]
+pub struct TargetMetadata {
+    pub title_id: Option<TitleId>,
+    pub title_name: Option<String>,
+    pub assets: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, Default)]
 pub struct ProfileMetadata {
+    /// Strips symbols from the vita elf. Defaults to `true` for `release` and `false` for other profiles.
     pub strip_symbols: Option<bool>,
 }
 
+/// Options of `vita-make-fself` for the eboot of an app.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct FselfOptions {
+    /// Safe apps (`-s`) have no access to restricted APIs and parts of the filesystem.
+    /// Defaults to `true`, setting it to `false` is the explicit opt-in into an unsafe app.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safe: Option<bool>,
+    /// Secret-safe app (`-ss`).
+    #[serde(default)]
+    pub secret_safe: bool,
+    /// Compresses the eboot (`-c`).
+    #[serde(default)]
+    pub compress: bool,
+    /// Address space layout randomization, enabled by default. Disabled with `-na`.
+    #[serde(default = "default_true")]
+    pub aslr: bool,
+    /// Memory size (`-m`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_size: Option<ByteSize>,
+    /// Physically contiguous memory size (`-pm`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phycont_memory_size: Option<ByteSize>,
+    /// Auth id of the self (`-a`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_id: Option<u64>,
+    /// The attribute word (`-at`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attribute: Option<u32>,
+    /// Flags from `vita_make_fself_flags` without a typed option, passed through as is.
+    #[serde(skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    #[schemars(skip)]
+    pub extra_flags: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// Must match the serde defaults, since packages without a `fself` table use this
+impl Default for FselfOptions {
+    fn default() -> Self {
+        Self {
+            safe: None,
+            secret_safe: false,
+            compress: false,
+            aslr: default_true(),
+            memory_size: None,
+            phycont_memory_size: None,
+            auth_id: None,
+            attribute: None,
+            extra_flags: Vec::new(),
+        }
+    }
+}
+
+impl FselfOptions {
+    pub fn is_safe(&self) -> bool {
+        self.safe.unwrap_or(true) || self.secret_safe
+    }
+
+    pub fn from_flags(flags: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            safe: Some(false),
+            ..Self::default()
+        };
+
+        let mut flags = flags.iter();
+
+        while let Some(flag) = flags.next() {
+            let mut value = || {
+                flags
+                    .next()
+                    .ok_or_else(|| format!("`{flag}` requires a value"))
+            };
+
+            match flag.as_str() {
+                "-s" => options.safe = Some(true),
+                "-ss" => options.secret_safe = true,
+                "-c" => options.compress = true,
+                "-na" => options.aslr = false,
+                "-m" => options.memory_size = Some(parse_number(value()?)?.into()),
+                "-pm" => options.phycont_memory_size = Some(parse_number(value()?)?.into()),
+                "-a" => options.auth_id = Some(parse_number(value()?)?),
+                "-at" => {
+                    let attribute = parse_number(value()?)?;
+                    options.attribute = Some(
+                        u32::try_from(attribute)
+                            .map_err(|_| format!("Attribute `{attribute:#x}` is too large"))?,
+                    );
+                }
+                // Values of unknown flags are not known flags either, so they are kept in order
+                flag => options.extra_flags.push(flag.to_string()),
+            }
+        }
+
+        Ok(options)
+    }
+
+    pub fn to_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+
+        if self.secret_safe {
+            flags.push("-ss".to_string());
+        } else if self.is_safe() {
+            flags.push("-s".to_string());
+        }
+
+        if self.compress {
+            flags.push("-c".to_string());
+        }
+
+        if !self.aslr {
+            flags.push("-na".to_string());
+        }
+
+        let options = [
+            ("-m", self.memory_size.map(|s| s.0)),
+            ("-pm", self.phycont_memory_size.map(|s| s.0)),
+            ("-a", self.auth_id),
+            ("-at", self.attribute.map(u64::from)),
+        ];
+
+        for (flag, value) in options {
+            if let Some(value) = value {
+                flags.push(flag.to_string());
+                flags.push(format!("{value:#x}"));
+            }
+        }
+
+        flags.extend(self.extra_flags.iter().cloned());
+        flags
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(s: &str) -> Result<u64, String> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("Invalid number `{s}`"))
+}
+
+/// Maximum sizes of the build outputs. The build fails if any of them is exceeded.
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+pub struct SizeLimits {
+    /// The size of `eboot.bin`.
+    pub eboot: Option<ByteSize>,
+    /// The size of the vpk file.
+    pub vpk: Option<ByteSize>,
+    /// The total size of all files in the assets directory.
+    pub assets: Option<ByteSize>,
+}
+
+/// Configuration of a taiHEN plugin, used by `cargo vita build plugin`.
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+pub struct PluginMetadata {
+    /// Kernel plugins are built as `.skprx`, user plugins as `.suprx`.
+    #[serde(default)]
+    pub kernel: bool,
+    /// Flags for `vita-make-fself`. Unlike apps, plugins are not built with `-s` by default.
+    #[serde(default)]
+    pub vita_make_fself_flags: Vec<String>,
+    /// A path to an exports yml for `vita-elf-create`, relative to the crate.
+    /// Can't be used together with `exports`.
+    pub exports_file: Option<String>,
+    /// Exports of the plugin, used to generate the exports yml.
+    pub exports: Option<PluginExports>,
+}
+
+fn default_module_start() -> String {
+    "module_start".to_string()
+}
+
+fn default_module_stop() -> String {
+    "module_stop".to_string()
+}
+
+/// The exports of a plugin, in the same structure as the exports yml of `vita-elf-create`.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default)]
+pub struct PluginExports {
+    /// The module name. Defaults to the package name.
+    #[serde(default, skip_serializing)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub attributes: u32,
+    #[serde(default)]
+    pub version: ModuleVersion,
+    #[serde(default)]
+    pub main: ModuleMain,
+    /// Exported libraries by their name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub modules: BTreeMap<String, ExportedLibrary>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ModuleVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl Default for ModuleVersion {
+    fn default() -> Self {
+        Self { major: 1, minor: 1 }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ModuleMain {
+    #[serde(default = "default_module_start")]
+    pub start: String,
+    #[serde(default = "default_module_stop")]
+    pub stop: String,
+}
+
+impl Default for ModuleMain {
+    fn default() -> Self {
+        Self {
+            start: default_module_start(),
+            stop: default_module_stop(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ExportedLibrary {
+    #[serde(default)]
+    pub syscall: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub functions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<String>,
+}
+
+/// Libraries imported from other modules, e.g. from third-party plugins.
+/// Stub libraries for them are generated and linked before the build.
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+pub struct ImportsMetadata {
+    /// Paths to NID databases in `db.yml` format, relative to the crate.
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Imported modules, in the same format as in `db.yml`.
+    #[serde(default)]
+    pub modules: BTreeMap<String, Module>,
+}
+
+impl ImportsMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.modules.is_empty()
+    }
+}
+
 impl Default for PackageMetadata {
     fn default() -> Self {
         Self {
             title_id: None,
+            title_id_prefix: None,
+            title_id_seed: None,
             title_name: None,
             assets: None,
             build_std: default_build_std(),
-            vita_make_fself_flags: default_vita_make_fself_flags(),
+            min_nightly: None,
+            vita_make_fself_flags: None,
+            fself: None,
             vita_mksfoex_flags: default_vita_mksfoex_flags(),
             profile: HashMap::new(),
+            size_limits: SizeLimits::default(),
+            plugin: PluginMetadata::default(),
+            imports: ImportsMetadata::default(),
+            firmware: Vec::new(),
+            bin: HashMap::new(),
+            example: HashMap::new(),
+            test: HashMap::new(),
+            features: BTreeMap::new(),
         }
     }
 }
 
-pub fn parse_crate_metadata(
-    artifact: Option<&Artifact>,
-) -> anyhow::Result<(PackageMetadata, Option<Package>, Utf8PathBuf)> {
-    let meta = MetadataCommand::new()
-        .exec()
-        .context("Failed to get cargo metadata")?;
+/// Returns `example`, `bench` or `test` for the artifacts that are not the main binaries of a package.
+pub fn artifact_kind(artifact: &Artifact) -> Option<&'static str> {
+    let has_kind = |kind: &str| artifact.target.kind.iter().any(|k| k == kind);
 
-    let pkg = match artifact {
-        Some(artifact) => meta.packages.iter().find(|p| p.id == artifact.package_id),
-        None => meta.workspace_default_packages().first().copied(),
-    };
+    if has_kind("example") {
+        Some("example")
+    } else if has_kind("bench") {
+        Some("bench")
+    } else if artifact.profile.test || has_kind("test") {
+        Some("test")
+    } else {
+        None
+    }
+}
 
-    if let Some(pkg) = pkg {
-        if let Some(metadata) = pkg.metadata.as_object() {
-            if let Some(metadata) = metadata.get("vita") {
-                let metadata = serde_json::from_value::<PackageMetadata>(metadata.clone())
-                    .context("Unable to deserialize `package.metadata.vita`")?;
+/// The output of `cargo metadata`, which can take seconds on large workspaces,
+/// so commands that need it several times query it once and share it.
+pub struct WorkspaceMetadata(Metadata);
 
-                return Ok((metadata, Some(pkg.clone()), meta.target_directory));
-            }
+impl WorkspaceMetadata {
+    pub fn load() -> anyhow::Result<Self> {
+        // Dependencies are not needed, and resolving them is the slow part
+        MetadataCommand::new()
+            .no_deps()
+            .exec()
+            .map(Self)
+            .context("Failed to get cargo metadata")
+    }
+
+    pub fn target_directory(&self) -> &Utf8Path {
+        &self.0.target_directory
+    }
+
+    pub fn workspace_root(&self) -> &Utf8Path {
+        &self.0.workspace_root
+    }
+
+    /// The vita metadata of the default package of the workspace.
+    pub fn package_metadata(&self) -> anyhow::Result<(PackageMetadata, Option<&Package>)> {
+        let pkg = self.0.workspace_default_packages().first().copied();
+
+        Ok((Self::metadata(pkg)?, pkg))
+    }
+
+    /// The vita metadata of the package of the artifact with the target overrides applied.
+    /// `default_title_id` is used when the package has no `title_id`,
+    /// and is also the prefix of the title ids derived for tests and examples.
+    pub fn artifact_metadata(
+        &self,
+        artifact: &Artifact,
+        default_title_id: Option<&TitleId>,
+    ) -> anyhow::Result<(PackageMetadata, Option<&Package>)> {
+        let pkg = self.0.packages.iter().find(|p| p.id == artifact.package_id);
+        let mut metadata = Self::metadata(pkg)?;
+
+        metadata.title_id = metadata.title_id.or_else(|| default_title_id.cloned());
+
+        if let Some(pkg) = pkg {
+            metadata.apply_overrides(&pkg.name, artifact);
         }
+
+        Ok((metadata, pkg))
     }
 
+    fn metadata(pkg: Option<&Package>) -> anyhow::Result<PackageMetadata> {
+        let Some((pkg, metadata)) = pkg.and_then(|pkg| Some((pkg, pkg.metadata.get("vita")?)))
+        else {
+            return Ok(PackageMetadata::default());
+        };
+
+        unknown_keys::deserialize(metadata, &pkg.manifest_path)
+            .context("Unable to deserialize `package.metadata.vita`")
+    }
+}
+
+pub fn parse_crate_metadata() -> anyhow::Result<(PackageMetadata, Option<Package>, Utf8PathBuf)> {
+    let workspace = WorkspaceMetadata::load()?;
+    let (metadata, pkg) = workspace.package_metadata()?;
+
     Ok((
-        PackageMetadata::default(),
+        metadata,
         pkg.cloned(),
-        meta.target_directory,
+        workspace.target_directory().to_owned(),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn default_fself_options_create_a_safe_app_with_aslr() {
+        let options = FselfOptions::default();
+
+        assert!(options.aslr);
+        assert!(options.is_safe());
+        assert_eq!(options.to_flags(), flags(&["-s"]));
+    }
+
+    #[test]
+    fn missing_fself_table_uses_defaults() {
+        let options = PackageMetadata::default().fself_options().unwrap();
+
+        assert_eq!(options.to_flags(), flags(&["-s"]));
+    }
+
+    #[test]
+    fn empty_fself_table_uses_defaults() {
+        let options = serde_json::from_str::<FselfOptions>("{}").unwrap();
+
+        assert_eq!(options.to_flags(), flags(&["-s"]));
+    }
+
+    #[test]
+    fn raw_flags_without_s_are_unsafe() {
+        let options = FselfOptions::from_flags(&[]).unwrap();
+
+        assert!(!options.is_safe());
+        assert!(options.to_flags().is_empty());
+    }
+
+    #[test]
+    fn raw_flags_round_trip() {
+        let raw = flags(&[
+            "-s",
+            "-c",
+            "-na",
+            "-m",
+            "0x1000",
+            "-a",
+            "0x2F00000000000001",
+        ]);
+        let options = FselfOptions::from_flags(&raw).unwrap();
+
+        assert!(options.compress);
+        assert!(!options.aslr);
+        assert_eq!(options.memory_size.map(|s| s.0), Some(0x1000));
+        assert_eq!(
+            options.to_flags(),
+            flags(&[
+                "-s",
+                "-c",
+                "-na",
+                "-m",
+                "0x1000",
+                "-a",
+                "0x2f00000000000001"
+            ])
+        );
+    }
+
+    #[test]
+    fn unknown_raw_flags_are_passed_through() {
+        let raw = flags(&["-s", "-e", "foo.yml", "-c"]);
+        let options = FselfOptions::from_flags(&raw).unwrap();
+
+        assert_eq!(options.extra_flags, flags(&["-e", "foo.yml"]));
+        assert_eq!(options.to_flags(), flags(&["-s", "-c", "-e", "foo.yml"]));
+    }
+
+    #[test]
+    fn raw_flags_require_values() {
+        assert!(FselfOptions::from_flags(&flags(&["-m"])).is_err());
+        assert!(FselfOptions::from_flags(&flags(&["-at", "0x100000000"])).is_err());
+    }
+
+    fn artifact(kind: &str, name: &str, test: bool) -> Artifact {
+        serde_json::from_value(serde_json::json!({
+            "package_id": "path+file:///app#0.1.0",
+            "manifest_path": "/app/Cargo.toml",
+            "target": {
+                "name": name,
+                "kind": [kind],
+                "crate_types": ["bin"],
+                "src_path": "/app/src/main.rs",
+                "edition": "2021",
+            },
+            "profile": {
+                "opt_level": "0",
+                "debug_assertions": true,
+                "overflow_checks": true,
+                "test": test,
+            },
+            "features": [],
+            "filenames": [],
+            "executable": null,
+            "fresh": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn derived_title_ids_are_stable() {
+        let prefix = "RUST".parse().unwrap();
+
+        assert_eq!(
+            TitleId::derive(&prefix, "app/test/it").to_string(),
+            TitleId::derive(&prefix, "app/test/it").to_string()
+        );
+        assert_ne!(
+            TitleId::derive(&prefix, "app/test/it").to_string(),
+            TitleId::derive(&prefix, "app/example/it").to_string()
+        );
+
+        let id = TitleId::derive(&prefix, "app/test/it").to_string();
+        assert!(id.starts_with("RUST"));
+        assert!(id.parse::<TitleId>().is_ok());
+    }
+
+    #[test]
+    fn only_derived_title_ids_have_a_seed() {
+        let mut meta = serde_json::from_value::<PackageMetadata>(serde_json::json!({
+            "title_id": "RUSTAPP01",
+            "test": { "pinned": { "title_id": "RUSTAPP02" } },
+        }))
+        .unwrap();
+        meta.apply_overrides("app", &artifact("test", "it", false));
+        assert_eq!(meta.title_id_seed.as_deref(), Some("app/test/it"));
+
+        let mut meta = serde_json::from_value::<PackageMetadata>(serde_json::json!({
+            "title_id": "RUSTAPP01",
+            "test": { "pinned": { "title_id": "RUSTAPP02" } },
+        }))
+        .unwrap();
+        meta.apply_overrides("app", &artifact("test", "pinned", false));
+        assert_eq!(meta.title_id_seed, None);
+    }
+
+    #[test]
+    fn bin_overrides_apply_to_the_bin() {
+        let mut meta = serde_json::from_value::<PackageMetadata>(serde_json::json!({
+            "title_id": "RUSTAPP01",
+            "bin": { "app": { "title_id": "RUSTAPP02" } },
+        }))
+        .unwrap();
+        meta.apply_overrides("app", &artifact("bin", "app", false));
+
+        assert_eq!(meta.title_id.unwrap().to_string(), "RUSTAPP02");
+    }
+
+    #[test]
+    fn bin_overrides_do_not_apply_to_unit_tests_of_the_bin() {
+        let mut meta = serde_json::from_value::<PackageMetadata>(serde_json::json!({
+            "title_id": "RUSTAPP01",
+            "bin": { "app": { "title_id": "RUSTAPP02" } },
+        }))
+        .unwrap();
+        meta.apply_overrides("app", &artifact("bin", "app", true));
+
+        let title_id = meta.title_id.unwrap().to_string();
+        assert_ne!(title_id, "RUSTAPP01");
+        assert_ne!(title_id, "RUSTAPP02");
+        assert!(title_id.starts_with("RUST"));
+    }
+
+    fn with_features(mut artifact: Artifact, features: &[&str]) -> Artifact {
+        artifact.features = features.iter().map(ToString::to_string).collect();
+        artifact
+    }
+
+    fn feature_metadata() -> PackageMetadata {
+        serde_json::from_value(serde_json::json!({
+            "title_id": "RUSTAPP01",
+            "title_name": "App",
+            "assets": "static",
+            // Declared out of order, overrides are applied alphabetically
+            "features": {
+                "lite": { "title_name": "App Lite" },
+                "demo": { "title_id": "RUSTDEMO1", "title_name": "App Demo", "assets": "demo" },
+            },
+            "bin": { "pinned": { "title_id": "RUSTPIN01" } },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn feature_overrides_apply_to_artifacts_built_with_the_feature() {
+        let mut meta = feature_metadata();
+        meta.apply_overrides(
+            "app",
+            &with_features(artifact("bin", "app", false), &["demo"]),
+        );
+
+        assert_eq!(meta.title_id.unwrap().to_string(), "RUSTDEMO1");
+        assert_eq!(meta.title_name.as_deref(), Some("App Demo"));
+        assert_eq!(meta.assets.as_deref(), Some("demo"));
+
+        let mut meta = feature_metadata();
+        meta.apply_overrides(
+            "app",
+            &with_features(artifact("bin", "app", false), &["std"]),
+        );
+
+        assert_eq!(meta.title_id.unwrap().to_string(), "RUSTAPP01");
+        assert_eq!(meta.title_name.as_deref(), Some("App"));
+        assert_eq!(meta.assets.as_deref(), Some("static"));
+    }
+
+    #[test]
+    fn feature_overrides_are_applied_in_alphabetical_order() {
+        let mut meta = feature_metadata();
+        meta.apply_overrides(
+            "app",
+            &with_features(artifact("bin", "app", false), &["lite", "demo"]),
+        );
+
+        // `lite` is applied after `demo`, and only overrides the title name
+        assert_eq!(meta.title_id.unwrap().to_string(), "RUSTDEMO1");
+        assert_eq!(meta.title_name.as_deref(), Some("App Lite"));
+        assert_eq!(meta.assets.as_deref(), Some("demo"));
+    }
+
+    #[test]
+    fn target_overrides_are_applied_after_feature_overrides() {
+        let mut meta = feature_metadata();
+        meta.apply_overrides(
+            "app",
+            &with_features(artifact("bin", "pinned", false), &["demo"]),
+        );
+
+        assert_eq!(meta.title_id.unwrap().to_string(), "RUSTPIN01");
+        assert_eq!(meta.title_name.as_deref(), Some("App Demo"));
+        assert_eq!(meta.assets.as_deref(), Some("demo"));
+    }
+
+    #[test]
+    fn feature_variants_of_tests_get_distinct_title_ids() {
+        let mut plain = feature_metadata();
+        plain.apply_overrides("app", &artifact("test", "it", false));
+
+        let mut lite = feature_metadata();
+        lite.apply_overrides(
+            "app",
+            &with_features(artifact("test", "it", false), &["lite"]),
+        );
+
+        assert_eq!(plain.title_id_seed.as_deref(), Some("app/test/it"));
+        assert_eq!(lite.title_id_seed.as_deref(), Some("app/test/it/lite"));
+        assert_ne!(
+            plain.title_id.unwrap().to_string(),
+            lite.title_id.unwrap().to_string()
+        );
+        // The derived name wins over the feature's, since the test has no overrides of its own
+        assert_eq!(lite.title_name.as_deref(), Some("App Lite test it"));
+    }
+
+    fn bytes(s: &str) -> Result<u64, String> {
+        s.parse::<ByteSize>().map(|size| size.0)
+    }
+
+    #[test]
+    fn byte_sizes_without_unit_are_bytes() {
+        assert_eq!(bytes("1024"), Ok(1024));
+        assert_eq!(bytes("1024 B"), Ok(1024));
+    }
+
+    #[test]
+    fn byte_size_units_are_case_insensitive() {
+        assert_eq!(bytes("2K"), Ok(2 << 10));
+        assert_eq!(bytes("2 MiB"), Ok(2 << 20));
+        assert_eq!(bytes("1gib"), Ok(1 << 30));
+        assert_eq!(bytes("1.5 MB"), Ok(1_500_000));
+        assert_eq!(bytes(" 3kb "), Ok(3_000));
+    }
+
+    #[test]
+    fn invalid_byte_sizes_are_rejected() {
+        assert!(bytes("10 TB").is_err());
+        assert!(bytes("MB").is_err());
+        assert!(bytes("1.2.3 MB").is_err());
+    }
+}
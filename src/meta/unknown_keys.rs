@@ -0,0 +1,241 @@
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::bail;
+use cargo_metadata::camino::Utf8Path;
+use colored::Colorize;
+use log::warn;
+use serde::{
+    de::{self, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+
+use crate::nids::{Library, Module};
+
+use super::{
+    ExportedLibrary, FselfOptions, ImportsMetadata, ModuleMain, ModuleVersion, PackageMetadata,
+    PluginExports, PluginMetadata, ProfileMetadata, SizeLimits, TargetMetadata,
+};
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Manifests that were already reported, since the metadata is parsed once per artifact.
+static REPORTED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Makes unknown keys in `package.metadata.vita` an error instead of a warning.
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Deserializes the metadata, reporting the keys that are not used by any of the fields.
+pub fn deserialize(
+    value: &serde_json::Value,
+    manifest_path: &Utf8Path,
+) -> anyhow::Result<PackageMetadata> {
+    let mut unknown = Vec::new();
+    let metadata = serde_ignored::deserialize(value, |path| unknown.push(keys(&path)))?;
+
+    if unknown.is_empty() {
+        return Ok(metadata);
+    }
+
+    let messages = unknown
+        .iter()
+        .map(|path| {
+            let hint = suggestion(path)
+                .map(|field| format!(", did you mean `{field}`?"))
+                .unwrap_or_default();
+            (path.join("."), hint)
+        })
+        .collect::<Vec<_>>();
+
+    if STRICT.load(Ordering::Relaxed) {
+        let keys = messages
+            .iter()
+            .map(|(key, hint)| format!("`{key}`{hint}"))
+            .collect::<Vec<_>>()
+            .join("\n  ");
+        bail!("Unknown keys in `package.metadata.vita` of {manifest_path}:\n  {keys}");
+    }
+
+    let first_report = REPORTED.lock().map_or(true, |mut reported| {
+        reported.insert(manifest_path.to_string())
+    });
+
+    if first_report {
+        for (key, hint) in messages {
+            warn!(
+                "{} `{key}` {} {manifest_path}{hint}",
+                "Unknown key".yellow(),
+                "in `package.metadata.vita` of".yellow()
+            );
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// The keys of the path, from `package.metadata.vita` down to the unknown key.
+fn keys(path: &serde_ignored::Path) -> Vec<String> {
+    let mut keys = match path {
+        serde_ignored::Path::Root => return Vec::new(),
+        serde_ignored::Path::Seq { parent, .. }
+        | serde_ignored::Path::Map { parent, .. }
+        | serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => keys(parent),
+    };
+
+    match path {
+        serde_ignored::Path::Seq { index, .. } => keys.push(index.to_string()),
+        serde_ignored::Path::Map { key, .. } => keys.push(key.clone()),
+        _ => {}
+    }
+
+    keys
+}
+
+/// The closest known key of the table the unknown key is in.
+fn suggestion(path: &[String]) -> Option<&'static str> {
+    let (key, table) = path.split_last()?;
+    let table = table.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let fields = match table.as_slice() {
+        [] => fields::<PackageMetadata>(),
+        ["profile", _] => fields::<ProfileMetadata>(),
+        ["bin" | "example" | "test" | "features", _] => fields::<TargetMetadata>(),
+        ["fself"] => fields::<FselfOptions>(),
+        ["size_limits"] => fields::<SizeLimits>(),
+        ["plugin"] => fields::<PluginMetadata>(),
+        ["plugin", "exports"] => fields::<PluginExports>(),
+        ["plugin", "exports", "version"] => fields::<ModuleVersion>(),
+        ["plugin", "exports", "main"] => fields::<ModuleMain>(),
+        ["plugin", "exports", "modules", _] => fields::<ExportedLibrary>(),
+        ["imports"] => fields::<ImportsMetadata>(),
+        ["imports", "modules", _] => fields::<Module>(),
+        ["imports", "modules", _, "libraries", _] => fields::<Library>(),
+        _ => return None,
+    };
+
+    fields
+        .iter()
+        .map(|field| (strsim::damerau_levenshtein(key, field), *field))
+        .filter(|(distance, _)| *distance <= 3)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, field)| field)
+}
+
+/// The field names of a struct with derived `Deserialize`.
+fn fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields = None;
+    let _ = T::deserialize(FieldsDeserializer(&mut fields));
+    fields.unwrap_or_default()
+}
+
+/// A deserializer that fails right away, capturing the fields serde asks it for.
+struct FieldsDeserializer<'a>(&'a mut Option<&'static [&'static str]>);
+
+impl<'de> Deserializer<'de> for FieldsDeserializer<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = Some(fields);
+        Err(de::Error::custom("fields captured"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn path(path: &str) -> Vec<String> {
+        path.split('.').map(ToString::to_string).collect()
+    }
+
+    fn unknown_keys(value: &serde_json::Value) -> Vec<String> {
+        let mut unknown = Vec::new();
+        let _: PackageMetadata =
+            serde_ignored::deserialize(value, |path| unknown.push(keys(&path).join("."))).unwrap();
+        unknown
+    }
+
+    #[test]
+    fn typos_are_suggested_in_their_table() {
+        for (path_to_key, expected) in [
+            ("title_idd", "title_id"),
+            ("buildstd", "build_std"),
+            ("profile.release.strip_symbol", "strip_symbols"),
+            ("bin.app.titel_name", "title_name"),
+            ("features.demo.asset", "assets"),
+            ("fself.safe_", "safe"),
+            ("plugin.kernal", "kernel"),
+            ("plugin.exports.main.strat", "start"),
+            ("plugin.exports.modules.MyLib.function", "functions"),
+            (
+                "imports.modules.SomePlugin.libraries.SomeLib.stub_name",
+                "stubname",
+            ),
+        ] {
+            assert_eq!(
+                suggestion(&path(path_to_key)),
+                Some(expected),
+                "path: {path_to_key}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_keys_without_a_close_field_have_no_suggestion() {
+        for path_to_key in [
+            "completely_unrelated",
+            "profile.release.opt_level",
+            // Tables that are not part of the metadata
+            "unknown.title_id",
+            "plugin.exports.modules.MyLib.functions.extra",
+        ] {
+            assert_eq!(suggestion(&path(path_to_key)), None, "path: {path_to_key}");
+        }
+
+        assert_eq!(suggestion(&[]), None);
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_by_their_path() {
+        let value = json!({
+            "title_idd": "RUSTAPP01",
+            "profile": { "release": { "strip_symbol": true } },
+            "plugin": { "exports": { "modules": { "MyLib": { "function": ["f"] } } } },
+        });
+
+        assert_eq!(
+            unknown_keys(&value),
+            [
+                "title_idd",
+                "profile.release.strip_symbol",
+                "plugin.exports.modules.MyLib.function",
+            ]
+        );
+    }
+}
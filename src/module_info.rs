@@ -0,0 +1,326 @@
+use std::{fmt::Display, io::Read};
+
+use anyhow::{bail, Context};
+use flate2::read::GzDecoder;
+use object::{
+    elf::{FileHeader32, PT_LOAD},
+    read::elf::{FileHeader, ProgramHeader},
+    LittleEndian,
+};
+use serde::{Deserialize, Serialize};
+
+/// The name of the coredump note with the modules loaded in the crashed process.
+static MODULE_INFO_NOTE: &[u8] = b"MODULE_INFO";
+
+// Layout of the `MODULE_INFO` note, as read by vita-parse-core: a `u32` header word and the number of modules,
+// followed by the modules. Every module has a fixed size header, its segments and a trailer.
+const MODULES_OFFSET: usize = 8;
+const MODULE_NAME_OFFSET: usize = 0x24;
+const MODULE_NAME_LEN: usize = 28;
+const SEGMENT_COUNT_OFFSET: usize = 0x50;
+const SEGMENTS_OFFSET: usize = 0x54;
+/// Every segment is `unk`, `attr`, `start`, `size` and `align` words.
+const SEGMENT_LEN: usize = 0x14;
+const SEGMENT_SIZE_OFFSET: usize = 0x0C;
+const MODULE_TRAILER_LEN: usize = 0x10;
+
+/// The offset of the name in `SceModuleInfo`, after the attributes and the version.
+const SCE_MODULE_INFO_NAME_OFFSET: usize = 4;
+const SCE_MODULE_INFO_NAME_LEN: usize = 27;
+
+/// What identifies a build of a module on the Vita: its name and the sizes of its segments.
+/// ASLR moves the segments around, but their sizes only change with the code and the data.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ModuleFingerprint {
+    pub name: String,
+    pub segment_sizes: Vec<u32>,
+}
+
+impl Display for ModuleFingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sizes = self
+            .segment_sizes
+            .iter()
+            .map(|size| format!("0x{size:X}"))
+            .collect::<Vec<_>>();
+
+        write!(f, "{} (segments {})", self.name, sizes.join(", "))
+    }
+}
+
+impl ModuleFingerprint {
+    /// Reads the module name from `SceModuleInfo` of a velf created by `vita-elf-create`,
+    /// and the sizes of its loadable segments.
+    pub fn from_velf(data: &[u8]) -> anyhow::Result<Self> {
+        let header = FileHeader32::<LittleEndian>::parse(data).context("Unable to parse velf")?;
+        let endian = header.endian()?;
+        let segments = header
+            .program_headers(endian, data)
+            .context("Unable to read velf segments")?;
+
+        // The top two bits of the entry are the segment index, and the rest is the offset of `SceModuleInfo` in it
+        let entry = header.e_entry(endian);
+        let segment = segments
+            .get((entry >> 30) as usize)
+            .context("Invalid `SceModuleInfo` segment in velf")?;
+        let module_info = segment
+            .data(endian, data)
+            .ok()
+            .and_then(|segment| segment.get((entry & 0x3FFF_FFFF) as usize..))
+            .context("Invalid `SceModuleInfo` offset in velf")?;
+
+        Ok(Self {
+            name: c_str(
+                module_info,
+                SCE_MODULE_INFO_NAME_OFFSET,
+                SCE_MODULE_INFO_NAME_LEN,
+            )
+            .context("Invalid `SceModuleInfo` name in velf")?,
+            segment_sizes: segments
+                .iter()
+                .filter(|segment| segment.p_type(endian) == PT_LOAD)
+                .map(|segment| segment.p_memsz(endian))
+                .collect(),
+        })
+    }
+
+    /// Reads the modules loaded in the crashed process from a coredump, either gzip compressed or not.
+    pub fn from_coredump(data: &[u8]) -> anyhow::Result<Vec<Self>> {
+        let mut decompressed = Vec::new();
+
+        let data = if data.starts_with(&[0x1f, 0x8b]) {
+            GzDecoder::new(data)
+                .read_to_end(&mut decompressed)
+                .context("Unable to decompress coredump")?;
+            &decompressed
+        } else {
+            data
+        };
+
+        let header =
+            FileHeader32::<LittleEndian>::parse(data).context("Unable to parse coredump")?;
+        let endian = header.endian()?;
+
+        for segment in header
+            .program_headers(endian, data)
+            .context("Unable to read coredump segments")?
+        {
+            let Some(mut notes) = segment.notes(endian, data)? else {
+                continue;
+            };
+
+            while let Some(note) = notes.next()? {
+                if note.name() == MODULE_INFO_NOTE {
+                    return parse_modules(note.desc());
+                }
+            }
+        }
+
+        bail!("Coredump has no module info")
+    }
+}
+
+fn parse_modules(data: &[u8]) -> anyhow::Result<Vec<ModuleFingerprint>> {
+    let count = u32_at(data, 4).context("Module info is truncated")?;
+    let mut offset = MODULES_OFFSET;
+    let mut modules = Vec::new();
+
+    for _ in 0..count {
+        let module = data.get(offset..).context("Module info is truncated")?;
+        let name = c_str(module, MODULE_NAME_OFFSET, MODULE_NAME_LEN)
+            .context("Module info is truncated")?;
+        let segment_count =
+            u32_at(module, SEGMENT_COUNT_OFFSET).context("Module info is truncated")? as usize;
+
+        let segment_sizes = (0..segment_count)
+            .map(|i| {
+                u32_at(
+                    module,
+                    SEGMENTS_OFFSET + i * SEGMENT_LEN + SEGMENT_SIZE_OFFSET,
+                )
+            })
+            .collect::<Option<Vec<_>>>()
+            .with_context(|| format!("Segments of module {name} are truncated"))?;
+
+        modules.push(ModuleFingerprint {
+            name,
+            segment_sizes,
+        });
+
+        offset += SEGMENTS_OFFSET + segment_count * SEGMENT_LEN + MODULE_TRAILER_LEN;
+    }
+
+    Ok(modules)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// A NUL-terminated string in a fixed size field.
+fn c_str(data: &[u8], offset: usize, len: usize) -> Option<String> {
+    let field = data.get(offset..offset + len)?;
+    let end = field.iter().position(|b| *b == 0).unwrap_or(len);
+
+    Some(String::from_utf8_lossy(&field[..end]).into_owned())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A little endian ELF32 with the given program headers, each with its data.
+    pub fn elf(e_type: u16, entry: u32, segments: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let phoff = 52;
+        let mut data_offset = phoff + 32 * segments.len();
+
+        let mut elf = Vec::new();
+        elf.extend(b"\x7fELF\x01\x01\x01");
+        elf.resize(16, 0);
+        elf.extend(e_type.to_le_bytes());
+        elf.extend(40_u16.to_le_bytes()); // EM_ARM
+        elf.extend(1_u32.to_le_bytes());
+        elf.extend(entry.to_le_bytes());
+        elf.extend(u32::try_from(phoff).unwrap().to_le_bytes());
+        elf.extend(0_u32.to_le_bytes()); // no section headers
+        elf.extend(0_u32.to_le_bytes());
+        elf.extend(52_u16.to_le_bytes());
+        elf.extend(32_u16.to_le_bytes());
+        elf.extend(u16::try_from(segments.len()).unwrap().to_le_bytes());
+        elf.extend([0; 6]);
+
+        for (p_type, memsz, data) in segments {
+            let offset = u32::try_from(data_offset).unwrap();
+            let filesz = u32::try_from(data.len()).unwrap();
+
+            for word in [*p_type, offset, 0, 0, filesz, *memsz, 0, 4] {
+                elf.extend(word.to_le_bytes());
+            }
+
+            data_offset += data.len();
+        }
+
+        for (_, _, data) in segments {
+            elf.extend(data);
+        }
+
+        elf
+    }
+
+    /// An ELF note with a 4-byte aligned name and descriptor.
+    pub fn note(name: &[u8], n_type: u32, desc: &[u8]) -> Vec<u8> {
+        let mut note = Vec::new();
+        note.extend(u32::try_from(name.len() + 1).unwrap().to_le_bytes());
+        note.extend(u32::try_from(desc.len()).unwrap().to_le_bytes());
+        note.extend(n_type.to_le_bytes());
+        note.extend(name);
+        note.push(0);
+        note.resize(note.len().next_multiple_of(4), 0);
+        note.extend(desc);
+        note.resize(note.len().next_multiple_of(4), 0);
+        note
+    }
+
+    /// A velf with `SceModuleInfo` at the start of the second segment.
+    pub fn velf(name: &str, text_size: u32, data_size: u32) -> Vec<u8> {
+        let mut module_info = vec![0; 0x5C];
+        module_info[4..4 + name.len()].copy_from_slice(name.as_bytes());
+
+        elf(
+            0xFE04,
+            1 << 30,
+            &[
+                (PT_LOAD, text_size, vec![0; 16]),
+                (PT_LOAD, data_size, module_info),
+            ],
+        )
+    }
+
+    /// A coredump with the given modules in its module info note.
+    pub fn coredump(modules: &[&ModuleFingerprint]) -> Vec<u8> {
+        let mut desc = vec![0; MODULES_OFFSET];
+        desc[4..8].copy_from_slice(&u32::try_from(modules.len()).unwrap().to_le_bytes());
+
+        for module in modules {
+            let mut entry = vec![0; SEGMENTS_OFFSET];
+            entry[MODULE_NAME_OFFSET..MODULE_NAME_OFFSET + module.name.len()]
+                .copy_from_slice(module.name.as_bytes());
+            entry[SEGMENT_COUNT_OFFSET..SEGMENTS_OFFSET].copy_from_slice(
+                &u32::try_from(module.segment_sizes.len())
+                    .unwrap()
+                    .to_le_bytes(),
+            );
+
+            for size in &module.segment_sizes {
+                let mut segment = vec![0; SEGMENT_LEN];
+                segment[SEGMENT_SIZE_OFFSET..SEGMENT_SIZE_OFFSET + 4]
+                    .copy_from_slice(&size.to_le_bytes());
+                entry.extend(segment);
+            }
+
+            entry.extend([0; MODULE_TRAILER_LEN]);
+            desc.extend(entry);
+        }
+
+        elf(
+            4, // ET_CORE
+            0,
+            &[(object::elf::PT_NOTE, 0, note(MODULE_INFO_NOTE, 0, &desc))],
+        )
+    }
+
+    fn fingerprint(name: &str, segment_sizes: &[u32]) -> ModuleFingerprint {
+        ModuleFingerprint {
+            name: name.to_string(),
+            segment_sizes: segment_sizes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn velf_fingerprint() {
+        assert_eq!(
+            ModuleFingerprint::from_velf(&velf("my_app", 0x1234, 0x560)).unwrap(),
+            fingerprint("my_app", &[0x1234, 0x560])
+        );
+    }
+
+    #[test]
+    fn coredump_modules() {
+        let kernel = fingerprint("SceLibKernel", &[0x8000]);
+        let app = fingerprint("my_app", &[0x1234, 0x560]);
+
+        assert_eq!(
+            ModuleFingerprint::from_coredump(&coredump(&[&kernel, &app])).unwrap(),
+            [kernel, app]
+        );
+    }
+
+    #[test]
+    fn compressed_coredump_modules() {
+        let app = fingerprint("my_app", &[0x1234, 0x560]);
+
+        let dump = coredump(&[&app]);
+        let mut compressed = Vec::new();
+        flate2::read::GzEncoder::new(&dump[..], flate2::Compression::fast())
+            .read_to_end(&mut compressed)
+            .unwrap();
+
+        assert_eq!(
+            ModuleFingerprint::from_coredump(&compressed).unwrap(),
+            [app]
+        );
+    }
+
+    #[test]
+    fn coredump_without_module_info() {
+        let dump = elf(
+            4,
+            0,
+            &[(object::elf::PT_NOTE, 0, note(b"CORE", 1, &[0; 4]))],
+        );
+
+        assert!(ModuleFingerprint::from_coredump(&dump).is_err());
+    }
+}
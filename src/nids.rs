@@ -0,0 +1,160 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+/// A NID database in the format of the `db.yml` files from `vita-headers`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct NidDb {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_firmware",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub firmware: Option<String>,
+    #[serde(default)]
+    pub modules: BTreeMap<String, Module>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone)]
+pub struct Module {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nid: Option<u32>,
+    #[serde(default)]
+    pub libraries: BTreeMap<String, Library>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone)]
+pub struct Library {
+    #[serde(default)]
+    pub kernel: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nid: Option<u32>,
+    /// Name of the stub library, without the `lib` prefix and `.a` suffix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stubname: Option<String>,
+    #[serde(default)]
+    pub functions: BTreeMap<String, u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, u32>,
+}
+
+impl Library {
+    /// The name of a function or a variable by its NID.
+    pub fn symbol_name(&self, nid: u32) -> Option<&str> {
+        self.functions
+            .iter()
+            .chain(&self.variables)
+            .find(|(_, n)| **n == nid)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The name of the stub library to pass to the linker.
+    pub fn stub_name(&self, library: &str) -> String {
+        self.stubname
+            .clone()
+            .unwrap_or_else(|| format!("{library}_stub"))
+    }
+}
+
+/// A function found in the NID database.
+pub struct FunctionEntry<'a> {
+    pub library_name: &'a str,
+    pub library: &'a Library,
+}
+
+/// Firmware versions like `3.60` are parsed by YAML as floats, so accept both strings and numbers.
+fn deserialize_firmware<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Number(f64),
+        String(String),
+    }
+
+    Ok(
+        Option::<Raw>::deserialize(deserializer)?.map(|raw| match raw {
+            Raw::Number(n) => format!("{n:.2}"),
+            Raw::String(s) => s,
+        }),
+    )
+}
+
+impl NidDb {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Unable to open NID database {}", path.display()))?;
+
+        serde_yaml::from_reader(file)
+            .with_context(|| format!("Unable to parse NID database {}", path.display()))
+    }
+
+    /// Loads all `.yml` files from the given path, which can be either a file or a directory.
+    pub fn load_all(path: &Path) -> anyhow::Result<Vec<Self>> {
+        if path.is_file() {
+            return Ok(vec![Self::load(path)?]);
+        }
+
+        if !path.is_dir() {
+            bail!("NID database {} does not exist", path.display());
+        }
+
+        WalkDir::new(path)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| {
+                e.path()
+                    .extension()
+                    .is_some_and(|ext| ext == "yml" || ext == "yaml")
+            })
+            .map(|e| Self::load(e.path()))
+            .collect()
+    }
+
+    pub fn libraries(&self) -> impl Iterator<Item = (&str, &Library)> {
+        self.modules
+            .values()
+            .flat_map(|m| m.libraries.iter())
+            .map(|(name, library)| (name.as_str(), library))
+    }
+
+    pub fn find_library(&self, nid: u32) -> Option<(&str, &Library)> {
+        self.libraries()
+            .find(|(_, library)| library.nid == Some(nid))
+    }
+
+    pub fn find_function(&self, name: &str) -> Option<FunctionEntry<'_>> {
+        self.libraries().find_map(|(library_name, library)| {
+            library
+                .functions
+                .contains_key(name)
+                .then_some(FunctionEntry {
+                    library_name,
+                    library,
+                })
+        })
+    }
+}
+
+/// The location of the NID database. Can be overridden with `VITA_NID_DB` environment variable,
+/// otherwise the database installed with `vita-headers` into `VITASDK` is used.
+pub fn default_db_path(sdk: &Path) -> PathBuf {
+    match env::var_os("VITA_NID_DB") {
+        Some(path) => PathBuf::from(path),
+        None => sdk.join("share").join("vita-headers").join("db"),
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::bail;
+use colored::Colorize;
+use log::{debug, warn};
+
+use crate::{check, meta::VITA_TARGET};
+
+/// `cfg`s that are always set, both for builds and for rust-analyzer.
+static VITA_CFGS: &[&str] = &[
+    "--cfg=mio_unsupported_force_poll_poll",
+    "--cfg=mio_unsupported_force_waker_pipe",
+];
+
+/// Flags that are always added to the rustflags configured by the user, besides [`VITA_CFGS`].
+static VITA_RUSTFLAGS: &[&str] = &[
+    // The build-id is used to match coredumps with the archived elf files in the symbol store
+    "-Clink-arg=-Wl,--build-id=sha1",
+];
+
+/// C and C++ flags for the Vita CPU, a quad-core Cortex-A9 with NEON.
+static VITA_CFLAGS: &str = "-mcpu=cortex-a9 -mfpu=neon -mfloat-abi=hard";
+
+/// The variable bindgen reads extra clang arguments from, when generating bindings for the Vita target.
+pub static BINDGEN_CLANG_ARGS_ENV: &str = "BINDGEN_EXTRA_CLANG_ARGS_armv7_sony_vita_newlibeabihf";
+
+/// The location of the installed `VitaSDK`.
+pub struct VitaSdk {
+    root: String,
+    rustflags: Vec<String>,
+}
+
+impl VitaSdk {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let root = env::var("VITASDK");
+        let root = root.or_else(|_| {
+            bail!(
+                "VITASDK environment variable isn't set. Please install the SDK \
+                    from https://vitasdk.org/ and set the VITASDK environment variable."
+            )
+        })?;
+
+        let mut rustflags = user_rustflags();
+        for flag in VITA_CFGS.iter().chain(VITA_RUSTFLAGS) {
+            if !rustflags.iter().any(|f| f == flag) {
+                rustflags.push((*flag).to_string());
+            }
+        }
+
+        debug!("{}: {}", "Effective rustflags".blue(), rustflags.join(" "));
+
+        Ok(Self { root, rustflags })
+    }
+
+    /// The SDK of whoever opens the project in an editor: paths are relative to `${env:VITASDK}`,
+    /// which VS Code substitutes, and rustflags only have the `cfg`s every build has.
+    pub fn portable() -> Self {
+        Self {
+            root: "${env:VITASDK}".to_string(),
+            rustflags: VITA_CFGS.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    pub fn rustflags(&self) -> &[String] {
+        &self.rustflags
+    }
+
+    pub fn root(&self) -> &Path {
+        Path::new(&self.root)
+    }
+
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.root().join(path)
+    }
+
+    pub fn binary(&self, binary: &str) -> PathBuf {
+        self.path("bin").join(binary)
+    }
+
+    /// Adds flags to the rustflags of all cargo commands created afterwards.
+    pub fn add_rustflags(&mut self, flags: impl IntoIterator<Item = String>) {
+        self.rustflags.extend(flags);
+        debug!(
+            "{}: {}",
+            "Effective rustflags".blue(),
+            self.rustflags.join(" ")
+        );
+    }
+
+    /// Prepends the SDK binaries to `PATH` of the command, so that tools can find each other.
+    pub fn set_path(&self, command: &mut Command) {
+        if let Ok(path) = env::var("PATH") {
+            let sdk_path = self.path("bin");
+            let path = format!("{}:{path}", sdk_path.display());
+            command.env("PATH", path);
+        }
+    }
+
+    /// Arguments for clang to parse the SDK headers the same way `arm-vita-eabi-gcc` does.
+    pub fn bindgen_clang_args(&self) -> Vec<String> {
+        let sysroot = self.path("arm-vita-eabi");
+
+        vec![
+            "--target=armv7a-none-eabihf".to_string(),
+            "-mcpu=cortex-a9".to_string(),
+            "-mfpu=neon".to_string(),
+            "-mfloat-abi=hard".to_string(),
+            format!("--sysroot={}", sysroot.display()),
+            "-isystem".to_string(),
+            sysroot.join("include").display().to_string(),
+            "-D__vita__".to_string(),
+        ]
+    }
+
+    /// Creates a `cargo` command for the given subcommand, with the environment
+    /// and the flags required to build for the Vita target.
+    pub fn cargo(&self, subcommand: &str, build_std: &str) -> Command {
+        let mut command = self.cargo_env(subcommand);
+
+        command
+            .arg("-Z")
+            .arg(format!("build-std={build_std}"))
+            .arg("--target")
+            .arg(VITA_TARGET);
+
+        command
+    }
+
+    /// A cargo command with the environment for the Vita target, but without `--target` and `-Z build-std`,
+    /// for subcommands that do not accept them.
+    pub fn cargo_env(&self, subcommand: &str) -> Command {
+        let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+
+        let mut command = Command::new(cargo);
+        self.set_path(&mut command);
+
+        command
+            // Encoded rustflags take priority over both `RUSTFLAGS` and cargo config,
+            // which are already merged into `self.rustflags`.
+            .env("CARGO_ENCODED_RUSTFLAGS", self.rustflags.join("\x1f"))
+            .env_remove("RUSTFLAGS")
+            .env("VITASDK", &self.root)
+            .arg(subcommand);
+
+        for (key, default) in self.target_env() {
+            command.pass_env(key, || &default);
+        }
+
+        command
+    }
+
+    /// Variables for the Vita target, which are set for cargo commands unless the user already set them.
+    pub fn target_env(&self) -> Vec<(&'static str, String)> {
+        let sysroot = self.path("arm-vita-eabi");
+        let path = |path: PathBuf| path.to_string_lossy().to_string();
+
+        vec![
+            // `TARGET_` prefixed variables are picked up by `cc`, `cmake` and `pkg-config` crates
+            // for the target only, so that build scripts compiling for the host are not affected.
+            ("TARGET_CC", "arm-vita-eabi-gcc".to_string()),
+            ("TARGET_CXX", "arm-vita-eabi-g++".to_string()),
+            ("TARGET_AR", "arm-vita-eabi-ar".to_string()),
+            ("TARGET_RANLIB", "arm-vita-eabi-ranlib".to_string()),
+            ("TARGET_CFLAGS", VITA_CFLAGS.to_string()),
+            ("TARGET_CXXFLAGS", VITA_CFLAGS.to_string()),
+            (
+                "TARGET_CMAKE_TOOLCHAIN_FILE",
+                path(self.path("share").join("vita.toolchain.cmake")),
+            ),
+            ("TARGET_PKG_CONFIG_SYSROOT_DIR", path(sysroot.clone())),
+            ("OPENSSL_LIB_DIR", path(sysroot.join("lib"))),
+            ("OPENSSL_INCLUDE_DIR", path(sysroot.join("include"))),
+            ("PKG_CONFIG", path(self.binary("arm-vita-eabi-pkg-config"))),
+            (
+                BINDGEN_CLANG_ARGS_ENV,
+                self.bindgen_clang_args()
+                    .iter()
+                    .map(|arg| shell_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+        ]
+    }
+}
+
+/// Rustflags the way cargo would pick them: `CARGO_ENCODED_RUSTFLAGS`, then `RUSTFLAGS`,
+/// then `[target.<triple>]` and matching `[target.'cfg(..)']` rustflags,
+/// or `[build].rustflags` from the cargo configuration.
+fn user_rustflags() -> Vec<String> {
+    if let Ok(flags) = env::var("CARGO_ENCODED_RUSTFLAGS") {
+        return flags
+            .split('\x1f')
+            .filter(|f| !f.is_empty())
+            .map(String::from)
+            .collect();
+    }
+
+    if let Ok(flags) = env::var("RUSTFLAGS") {
+        return flags.split_whitespace().map(String::from).collect();
+    }
+
+    check::config_rustflags(VITA_TARGET).unwrap_or_else(|err| {
+        warn!(
+            "{} {err:?}",
+            "Unable to read rustflags from cargo config, they will be ignored:".yellow()
+        );
+        Vec::new()
+    })
+}
+
+/// bindgen splits extra clang arguments like a POSIX shell does.
+fn shell_quote(arg: &str) -> String {
+    if arg
+        .chars()
+        .any(|c| c.is_whitespace() || "'\"\\$`".contains(c))
+    {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    } else {
+        arg.to_string()
+    }
+}
+
+trait CommandExt {
+    fn pass_env<K, V>(&mut self, key: K, default: impl Fn() -> V) -> &mut Command
+    where
+        K: AsRef<str>,
+        V: AsRef<str>;
+}
+
+impl CommandExt for Command {
+    fn pass_env<K, V>(&mut self, key: K, default: impl Fn() -> V) -> &mut Command
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let key = key.as_ref();
+        match env::var(key) {
+            Ok(val) => self.env(key, val),
+            Err(_) => self.env(key, default().as_ref()),
+        }
+    }
+}
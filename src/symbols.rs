@@ -0,0 +1,375 @@
+use std::{
+    fmt::Write,
+    fs::{self, File},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use cargo_metadata::camino::{Utf8Path, Utf8PathBuf};
+use colored::Colorize;
+use log::{debug, info, warn};
+use object::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::module_info::ModuleFingerprint;
+
+/// What is known about an archived ELF, stored next to it.
+static ENTRY_FILE: &str = "entry.json";
+
+#[derive(Serialize, Deserialize, Default)]
+struct Entry {
+    /// The title id of the app, or another name the archived builds are grouped by when pruning.
+    title: String,
+    /// The module created from the ELF, once the velf is created.
+    module: Option<ModuleFingerprint>,
+}
+
+/// A content-addressed directory of unstripped ELF files, keyed by their GNU build-id.
+///
+/// Every ELF is stored as `<root>/<build-id>/<file name>`, so that a coredump
+/// can be matched with the exact binary that produced it, long after the
+/// target directory has been rebuilt. Only the `keep` most recently archived
+/// ELF files of every title are kept.
+pub struct SymbolStore {
+    root: Utf8PathBuf,
+    keep: usize,
+}
+
+impl SymbolStore {
+    pub fn new(root: Utf8PathBuf, keep: usize) -> Self {
+        Self { root, keep }
+    }
+
+    /// Copies the ELF into the store. Returns the build-id the ELF was stored under,
+    /// or `None` if the ELF has no build-id note.
+    pub fn archive(&self, elf: &Utf8Path, title: &str) -> anyhow::Result<Option<String>> {
+        let data = fs::read(elf).context("Unable to read elf file")?;
+
+        let Some(build_id) = build_id(&data)? else {
+            warn!(
+                "{} {elf}",
+                "Unable to archive elf without a GNU build-id:".yellow()
+            );
+            return Ok(None);
+        };
+
+        let dir = self.root.join(&build_id);
+        let dest = dir.join(elf.file_name().context("Elf path has no file name")?);
+
+        if dest.exists() {
+            debug!("{} {dest}", "Elf is already archived in".blue());
+
+            // Rebuilding the same elf counts as a recent build, so it is not pruned
+            File::options()
+                .append(true)
+                .open(&dest)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+                .context("Unable to update archived elf")?;
+
+            return Ok(Some(build_id));
+        }
+
+        fs::create_dir_all(&dir).context("Unable to create symbol store directory")?;
+        fs::write(&dest, &data).context("Unable to write elf to symbol store")?;
+        self.write_entry(
+            &build_id,
+            &Entry {
+                title: title.to_string(),
+                module: None,
+            },
+        )?;
+
+        info!("{} {dest}", "Archived elf to".blue());
+
+        self.prune(title)?;
+
+        Ok(Some(build_id))
+    }
+
+    /// Records the module of a velf created from an archived ELF,
+    /// so that the ELF can be found by the modules listed in a coredump.
+    pub fn record_module(&self, elf: &Utf8Path, velf: &Utf8Path) -> anyhow::Result<()> {
+        let Some(build_id) = build_id(&fs::read(elf).context("Unable to read elf file")?)? else {
+            return Ok(());
+        };
+
+        let Some(mut entry) = self.entry(&build_id) else {
+            return Ok(());
+        };
+
+        let module = ModuleFingerprint::from_velf(&fs::read(velf).context("Unable to read velf")?)?;
+        debug!("{} {module}", "Recording module".blue());
+        entry.module = Some(module);
+
+        self.write_entry(&build_id, &entry)
+    }
+
+    /// Returns the path to an archived ELF with the given build-id.
+    pub fn find(&self, build_id: &str) -> Option<Utf8PathBuf> {
+        fs::read_dir(self.root.join(build_id))
+            .ok()?
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+            .filter(|e| e.file_name() != ENTRY_FILE)
+            .find_map(|e| Utf8PathBuf::from_path_buf(e.path()).ok())
+    }
+
+    /// Returns the archived copy of the ELF with the same build-id,
+    /// which still has all of its symbols even if the ELF itself was stripped since.
+    pub fn find_for_elf(&self, elf: &Utf8Path) -> anyhow::Result<Option<Utf8PathBuf>> {
+        let data = fs::read(elf).context("Unable to read elf file")?;
+
+        Ok(build_id(&data)?.and_then(|id| self.find(&id)))
+    }
+
+    /// Returns the most recently archived ELF any of the modules was created from, with the module.
+    pub fn find_for_modules<'a>(
+        &self,
+        modules: &'a [ModuleFingerprint],
+    ) -> Option<(&'a ModuleFingerprint, Utf8PathBuf)> {
+        self.archived().into_iter().find_map(|(_, build_id)| {
+            let recorded = self.entry(&build_id)?.module?;
+            let module = modules.iter().find(|module| **module == recorded)?;
+
+            Some((module, self.find(&build_id)?))
+        })
+    }
+
+    fn entry(&self, build_id: &str) -> Option<Entry> {
+        let entry = fs::read_to_string(self.root.join(build_id).join(ENTRY_FILE)).ok()?;
+        serde_json::from_str(&entry).ok()
+    }
+
+    fn write_entry(&self, build_id: &str, entry: &Entry) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(entry).context("Unable to serialize entry")?;
+
+        fs::write(self.root.join(build_id).join(ENTRY_FILE), json)
+            .context("Unable to write symbol store entry")
+    }
+
+    /// The build-ids of the archived ELF files, the most recently archived first.
+    fn archived(&self) -> Vec<(SystemTime, String)> {
+        let Ok(entries) = fs::read_dir(&self.root) else {
+            return Vec::new();
+        };
+
+        let mut archived = entries
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter_map(|build_id| {
+                let modified = fs::metadata(self.find(&build_id)?).ok()?.modified().ok()?;
+                Some((modified, build_id))
+            })
+            .collect::<Vec<_>>();
+
+        archived.sort_by(|a, b| b.cmp(a));
+        archived
+    }
+
+    /// Removes all but the `keep` most recently archived ELF files of the title.
+    fn prune(&self, title: &str) -> anyhow::Result<()> {
+        let old = self
+            .archived()
+            .into_iter()
+            .filter(|(_, build_id)| self.entry(build_id).is_some_and(|e| e.title == title))
+            .skip(self.keep);
+
+        for (_, build_id) in old {
+            let dir = self.root.join(build_id);
+            debug!("{} {dir}", "Removing old elf from symbol store".blue());
+            fs::remove_dir_all(&dir).context("Unable to prune symbol store")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn build_id(elf: &[u8]) -> anyhow::Result<Option<String>> {
+    let file = object::File::parse(elf).context("Unable to parse elf file")?;
+    let build_id = file.build_id().context("Unable to read elf build-id")?;
+
+    Ok(build_id.map(hex))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use object::elf::{NT_GNU_BUILD_ID, PT_LOAD, PT_NOTE};
+
+    use super::*;
+    use crate::module_info::tests::{elf, note, velf};
+
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        root: Utf8PathBuf,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let root = Utf8PathBuf::from_path_buf(dir.path().to_path_buf()).unwrap();
+
+            Self { _dir: dir, root }
+        }
+
+        fn store(&self, keep: usize) -> SymbolStore {
+            SymbolStore::new(self.root.join("store"), keep)
+        }
+
+        /// Writes an ELF with the build-id to the build directory.
+        fn elf(&self, name: &str, build_id: &[u8]) -> Utf8PathBuf {
+            let path = self.root.join("build").join(name);
+            let data = elf(
+                2,
+                0,
+                &[
+                    (PT_NOTE, 0, note(b"GNU", NT_GNU_BUILD_ID, build_id)),
+                    (PT_LOAD, 0x100, vec![0; 16]),
+                ],
+            );
+
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, data).unwrap();
+            path
+        }
+
+        fn velf(&self, name: &str, text_size: u32) -> Utf8PathBuf {
+            let path = self.root.join("build").join(name).with_extension("velf");
+            fs::write(&path, velf(name, text_size, 0x100)).unwrap();
+            path
+        }
+    }
+
+    /// Archives are ordered by the modification time, which is not precise on every filesystem.
+    fn tick() {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn archive_and_find_by_build_id() {
+        let fixture = Fixture::new();
+        let store = fixture.store(20);
+        let elf = fixture.elf("app.elf", &[0xAB; 20]);
+
+        let build_id = store.archive(&elf, "RUSTAPP01").unwrap().unwrap();
+
+        assert_eq!(build_id, "ab".repeat(20));
+        let archived = store.find(&build_id).unwrap();
+        assert_eq!(archived.file_name(), Some("app.elf"));
+        assert_eq!(fs::read(&archived).unwrap(), fs::read(&elf).unwrap());
+        assert_eq!(store.find_for_elf(&elf).unwrap(), Some(archived));
+    }
+
+    #[test]
+    fn elf_without_build_id_is_not_archived() {
+        let fixture = Fixture::new();
+        let store = fixture.store(20);
+        let path = fixture.root.join("no-build-id.elf");
+        fs::write(&path, elf(2, 0, &[(PT_LOAD, 0x100, vec![0; 16])])).unwrap();
+
+        assert_eq!(store.archive(&path, "RUSTAPP01").unwrap(), None);
+    }
+
+    #[test]
+    fn find_by_recorded_module() {
+        let fixture = Fixture::new();
+        let store = fixture.store(20);
+
+        let old = fixture.elf("app.elf", &[1; 20]);
+        store.archive(&old, "RUSTAPP01").unwrap();
+        store
+            .record_module(&old, &fixture.velf("app", 0x1000))
+            .unwrap();
+        let old = store.find_for_elf(&old).unwrap().unwrap();
+
+        tick();
+        let new = fixture.elf("app.elf", &[2; 20]);
+        store.archive(&new, "RUSTAPP01").unwrap();
+        store
+            .record_module(&new, &fixture.velf("app", 0x2000))
+            .unwrap();
+
+        let modules = [
+            ModuleFingerprint {
+                name: "SceLibKernel".to_string(),
+                segment_sizes: vec![0x1000, 0x100],
+            },
+            ModuleFingerprint {
+                name: "app".to_string(),
+                segment_sizes: vec![0x1000, 0x100],
+            },
+        ];
+
+        let (module, elf) = store.find_for_modules(&modules).unwrap();
+        assert_eq!(module.name, "app");
+        assert_eq!(elf, old);
+
+        let unknown = [ModuleFingerprint {
+            name: "app".to_string(),
+            segment_sizes: vec![0x3000, 0x100],
+        }];
+        assert!(store.find_for_modules(&unknown).is_none());
+    }
+
+    #[test]
+    fn prune_keeps_recent_builds_of_every_title() {
+        let fixture = Fixture::new();
+        let store = fixture.store(2);
+
+        let release = fixture.elf("app.elf", &[1; 20]);
+        let release_id = store.archive(&release, "RUSTAPP01").unwrap().unwrap();
+
+        // Many builds of tests must not evict the build of the app
+        for i in 2..6 {
+            tick();
+            let test = fixture.elf("test.elf", &[i; 20]);
+            store.archive(&test, "RUST01234").unwrap();
+        }
+
+        assert!(store.find(&release_id).is_some());
+        assert_eq!(store.archived().len(), 3);
+
+        tick();
+        store
+            .archive(&fixture.elf("app.elf", &[10; 20]), "RUSTAPP01")
+            .unwrap();
+        tick();
+        store
+            .archive(&fixture.elf("app.elf", &[11; 20]), "RUSTAPP01")
+            .unwrap();
+
+        assert!(store.find(&release_id).is_none());
+        assert_eq!(store.archived().len(), 4);
+    }
+
+    #[test]
+    fn rebuilding_an_archived_elf_keeps_it() {
+        let fixture = Fixture::new();
+        let store = fixture.store(2);
+
+        let first = fixture.elf("first.elf", &[1; 20]);
+        let first_id = store.archive(&first, "RUSTAPP01").unwrap().unwrap();
+        tick();
+        store
+            .archive(&fixture.elf("second.elf", &[2; 20]), "RUSTAPP01")
+            .unwrap();
+        tick();
+        store.archive(&first, "RUSTAPP01").unwrap();
+        tick();
+        store
+            .archive(&fixture.elf("third.elf", &[3; 20]), "RUSTAPP01")
+            .unwrap();
+
+        assert!(store.find(&first_id).is_some());
+        assert!(store.find(&"02".repeat(20)).is_none());
+    }
+}